@@ -36,6 +36,8 @@ kubectl apply -f deploy/k8s/00-namespace.yaml
 echo "🔐 Creando secrets..."
 kubectl create secret generic rust-api-secrets \
   --from-literal=jwt-secret=local_dev_secret_key \
+  --from-literal=email-verification-secret=local_dev_email_secret \
+  --from-literal=magic-link-secret=local_dev_magic_link_secret \
   --from-literal=postgres-password=postgres \
   --namespace=rust-api \
   --dry-run=client -o yaml | kubectl apply -f -
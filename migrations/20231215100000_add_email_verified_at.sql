@@ -0,0 +1,5 @@
+-- Verificación de email
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMP;
+
+-- Los usuarios existentes son anteriores a la verificación: se dan por verificados
+UPDATE users SET email_verified_at = NOW() WHERE email_verified_at IS NULL;
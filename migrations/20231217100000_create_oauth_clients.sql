@@ -0,0 +1,22 @@
+-- Clientes OAuth2 (client_credentials) para servicios internos
+-- No pertenecen a ningún usuario: son principals de servicio con sus propios scopes.
+-- Solo se guarda el hash SHA-256 del secreto; el valor en claro se muestra una única vez.
+CREATE TABLE IF NOT EXISTS clients (
+    id SERIAL PRIMARY KEY,
+    client_id VARCHAR(64) NOT NULL UNIQUE,
+    name VARCHAR(100) NOT NULL,
+    secret_hash VARCHAR(64) NOT NULL,
+    scopes TEXT[] NOT NULL DEFAULT '{}', -- Permisos que el cliente puede pedir
+    created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    last_used_at TIMESTAMP,
+    revoked_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Nuevo permiso para gestionar clientes (solo admin)
+INSERT INTO permissions (name) VALUES ('clients:manage') ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id FROM roles r JOIN permissions p ON p.name = 'clients:manage'
+WHERE r.name = 'admin'
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,25 @@
+-- Registro de auditoría (suplantación de usuarios por soporte, etc.)
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    actor_id INTEGER REFERENCES users(id) ON DELETE SET NULL,   -- Quién actúa realmente
+    subject_id INTEGER REFERENCES users(id) ON DELETE SET NULL, -- En nombre de quién
+    action VARCHAR(100) NOT NULL,                                -- ej. 'impersonation.start'
+    method VARCHAR(10),
+    path TEXT,
+    status SMALLINT,
+    token_id VARCHAR(64),                                        -- jti del token de suplantación
+    ip VARCHAR(64),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor_id ON audit_log(actor_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_subject_id ON audit_log(subject_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_token_id ON audit_log(token_id);
+
+-- Permiso para suplantar usuarios (solo admin)
+INSERT INTO permissions (name) VALUES ('users:impersonate') ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id FROM roles r JOIN permissions p ON p.name = 'users:impersonate'
+WHERE r.name = 'admin'
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,15 @@
+-- Passkeys (WebAuthn): una fila por autenticador registrado
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    credential_id TEXT NOT NULL UNIQUE,       -- base64url del id que asigna el autenticador
+    public_key BYTEA NOT NULL,                -- Clave pública en formato COSE
+    algorithm INTEGER NOT NULL,               -- Algoritmo COSE (-7 ES256, -257 RS256)
+    sign_count BIGINT NOT NULL DEFAULT 0,     -- Detecta autenticadores clonados
+    transports TEXT[] NOT NULL DEFAULT '{}',  -- usb, nfc, ble, internal, hybrid...
+    name VARCHAR(100) NOT NULL,
+    last_used_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_webauthn_credentials_user_id ON webauthn_credentials(user_id);
@@ -0,0 +1,2 @@
+-- Nombre visible opcional (el username sigue siendo el identificador de login)
+ALTER TABLE users ADD COLUMN IF NOT EXISTS display_name VARCHAR(100);
@@ -0,0 +1,9 @@
+-- Borrado de cuentas (RGPD): DELETE /me marca la cuenta y un job la purga tras el periodo de gracia
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS idx_users_deleted_at ON users(deleted_at) WHERE deleted_at IS NOT NULL;
+
+-- Actividad atribuible a un usuario (entra en su exportación; al purgarlo queda anónima)
+ALTER TABLE recent_activities ADD COLUMN IF NOT EXISTS user_id INTEGER REFERENCES users(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_recent_activities_user_id ON recent_activities(user_id);
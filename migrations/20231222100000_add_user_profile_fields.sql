@@ -0,0 +1,6 @@
+-- Perfil de usuario: bio, zona horaria, idioma y avatar
+ALTER TABLE users ADD COLUMN IF NOT EXISTS bio VARCHAR(500);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS timezone VARCHAR(64);  -- IANA, ej. Europe/Madrid
+ALTER TABLE users ADD COLUMN IF NOT EXISTS locale VARCHAR(35);    -- BCP 47, ej. es-ES
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_key VARCHAR(255);  -- Prefijo en el storage (miniaturas)
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_url TEXT;          -- URL pública de la miniatura grande
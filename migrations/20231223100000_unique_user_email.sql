@@ -0,0 +1,4 @@
+-- Un email identifica a una sola cuenta activa (forgot-password, magic link...).
+-- Sin distinguir mayúsculas; las cuentas borradas pendientes de purga no cuentan.
+-- Si ya hay duplicados hay que resolverlos a mano antes de aplicar esta migración.
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email_lower ON users (LOWER(email)) WHERE deleted_at IS NULL;
@@ -0,0 +1,48 @@
+// ============================================================================
+// API KEYS (clientes máquina)
+// ============================================================================
+//
+// Formato de la clave: rk_<prefix>_<secret>
+//   - prefix: 8 caracteres hex, visibles en los listados para reconocer la clave
+//   - secret: 32 bytes aleatorios en base64url
+//
+// En BBDD solo se guarda SHA-256(clave). Al ser claves aleatorias de alta
+// entropía no necesitamos un hash lento como bcrypt: SHA-256 permite buscar la
+// clave directamente por su hash con un índice UNIQUE.
+//
+// ============================================================================
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Caducidad máxima que se puede pedir al crear una clave (10 años)
+pub const MAX_EXPIRES_IN_DAYS: i64 = 3650;
+
+pub struct GeneratedApiKey {
+    pub key: String,
+    pub prefix: String,
+    pub hash: String,
+}
+
+pub fn generate_api_key() -> GeneratedApiKey {
+    let mut rng = rand::thread_rng();
+
+    let mut prefix_bytes = [0u8; 4];
+    rng.fill_bytes(&mut prefix_bytes);
+    let prefix: String = prefix_bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let mut secret = [0u8; 32];
+    rng.fill_bytes(&mut secret);
+
+    let key = format!("rk_{}_{}", prefix, URL_SAFE_NO_PAD.encode(secret));
+    let hash = hash_api_key(&key);
+
+    GeneratedApiKey { key, prefix, hash }
+}
+
+pub fn hash_api_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
@@ -0,0 +1,39 @@
+// ============================================================================
+// AUDITORÍA
+// ============================================================================
+//
+// Deja constancia en la tabla audit_log de las acciones sensibles:
+// la suplantación de usuarios (inicio, fin y CADA request hecha con un token
+// de suplantación), las peticiones RGPD (exportación y borrado de la cuenta)
+// y los cambios de contraseña.
+//
+// Un fallo al escribir la auditoría de una request suplantada no debe pasar
+// desapercibido: se registra como error en los logs.
+//
+// ============================================================================
+
+use sqlx::PgPool;
+use crate::{db, error::AppError};
+
+pub const IMPERSONATION_START: &str = "impersonation.start";
+pub const IMPERSONATION_STOP: &str = "impersonation.stop";
+pub const IMPERSONATION_REQUEST: &str = "impersonation.request";
+pub const ACCOUNT_EXPORT: &str = "account.export";
+pub const ACCOUNT_DELETION: &str = "account.deletion_requested";
+pub const PASSWORD_CHANGE: &str = "account.password_changed";
+
+#[derive(Debug, Default)]
+pub struct AuditEvent<'a> {
+    pub actor_id: Option<i32>,
+    pub subject_id: Option<i32>,
+    pub action: &'a str,
+    pub method: Option<&'a str>,
+    pub path: Option<&'a str>,
+    pub status: Option<u16>,
+    pub token_id: Option<&'a str>,
+    pub ip: Option<&'a str>,
+}
+
+pub async fn record(pool: &PgPool, event: AuditEvent<'_>) -> Result<(), AppError> {
+    db::insert_audit_event(pool, &event).await
+}
@@ -1,17 +1,16 @@
-use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey, TokenData};
-use chrono::{Utc, Duration};
-use crate::{models::Claims, error::AppError};
+use jsonwebtoken::{encode, decode, decode_header, Header, Validation, TokenData};
+use chrono::{DateTime, Utc, Duration};
+use crate::{models::{ActorClaim, Claims}, error::AppError, extractors::ClientInfo, jwt_keys, password};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
 use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 use std::env;
 
-fn get_jwt_secret() -> Vec<u8> {
-    env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "fallback_secret_key".to_string())
-        .into_bytes()
-}
-
 fn get_jwt_expiration_hours() -> i64 {
     // Cambiado a 15 minutos para refresh token pattern
     env::var("JWT_EXPIRATION_MINUTES")
@@ -20,118 +19,698 @@ fn get_jwt_expiration_hours() -> i64 {
         .unwrap_or(15)
 }
 
-pub fn hash_password(password: &str) -> Result<String, AppError> {
-    bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|e| {
-        println!("Error hashing password: {}", e);
-        AppError::DatabaseError(sqlx::Error::Protocol("Error hashing password".into())) // Simplificación error
-    })
+/// Vida máxima de un access token, en segundos
+pub fn access_token_lifetime_seconds() -> i64 {
+    get_jwt_expiration_hours() * 60
 }
 
-pub fn verify_password(password: &str, hash: &str) -> Result<bool, AppError> {
-    bcrypt::verify(password, hash).map_err(|e| {
-        println!("Error verifying password: {}", e);
-        AppError::DatabaseError(sqlx::Error::Protocol("Error verifying password".into()))
-    })
+// El algoritmo concreto (Argon2id, bcrypt...) lo decide el módulo `password`.
+// Hashear y verificar cuesta decenas de ms de CPU: se hace en el pool de
+// hilos bloqueantes para no parar los workers del runtime.
+
+async fn run_blocking<T: Send + 'static>(
+    job: impl FnOnce() -> Result<T, AppError> + Send + 'static,
+) -> Result<T, AppError> {
+    tokio::task::spawn_blocking(job).await.map_err(|e| {
+        tracing::error!("Tarea de hashing abortada: {}", e);
+        AppError::DatabaseError(sqlx::Error::Protocol("Password hashing error".into()))
+    })?
+}
+
+pub async fn hash_password(password: &str) -> Result<String, AppError> {
+    let password = password.to_owned();
+    run_blocking(move || password::hashers().hash(&password)).await
+}
+
+pub async fn verify_password(password: &str, hash: &str) -> Result<bool, AppError> {
+    let (password, hash) = (password.to_owned(), hash.to_owned());
+    run_blocking(move || password::hashers().verify(&password, &hash)).await
+}
+
+/// ¿Hay que rehashear (algoritmo o parámetros antiguos)?
+pub fn password_needs_rehash(hash: &str) -> bool {
+    password::hashers().needs_rehash(hash)
+}
+
+/// Verificación del login: tarda lo mismo exista o no el usuario y sea cual sea
+/// el algoritmo de su hash (ver `password::PasswordHashers::verify_login`)
+pub async fn verify_login_password(password: &str, hash: Option<&str>) -> Result<bool, AppError> {
+    let (password, hash) = (password.to_owned(), hash.map(str::to_owned));
+    run_blocking(move || password::hashers().verify_login(&password, hash.as_deref())).await
+}
+
+pub fn create_jwt(username: &str, roles: &[String], session_id: Option<&str>) -> Result<String, AppError> {
+    let mut claims = new_claims(username);
+    claims.roles = roles.to_vec();
+    claims.sid = session_id.map(str::to_owned);
+
+    sign_jwt(&claims, "access")
+}
+
+/// Access token de un service principal (grant client_credentials).
+/// Sin roles ni sesión: solo los scopes concedidos al cliente.
+pub fn create_client_jwt(client_id: &str, scopes: &[String]) -> Result<String, AppError> {
+    let mut claims = new_claims(client_id);
+    claims.client_id = Some(client_id.to_owned());
+    claims.scope = Some(scopes.join(" "));
+
+    sign_jwt(&claims, "client_credentials")
+}
+
+fn impersonation_lifetime_minutes() -> i64 {
+    env::var("IMPERSONATION_TTL_MINUTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10)
+}
+
+/// Token de suplantación: `sub` es el usuario suplantado y `act` el admin.
+/// Vida corta, sin sesión ni refresh token: al caducar hay que pedir otro.
+pub fn create_impersonation_jwt(
+    username: &str,
+    roles: &[String],
+    actor: &str,
+) -> Result<(String, Claims), AppError> {
+    let mut claims = new_claims(username);
+    let now = Utc::now();
+    let lifetime = Duration::minutes(impersonation_lifetime_minutes().min(get_jwt_expiration_hours()));
+    claims.exp = (now + lifetime).timestamp() as usize;
+    claims.roles = roles.to_vec();
+    claims.act = Some(ActorClaim { sub: actor.to_owned() });
+
+    let token = sign_jwt(&claims, "impersonation")?;
+    Ok((token, claims))
 }
 
-pub fn create_jwt(username: &str) -> Result<String, AppError> {
-    let expiration = Utc::now()
+fn new_claims(subject: &str) -> Claims {
+    let now = Utc::now();
+    let expiration = now
         .checked_add_signed(Duration::minutes(get_jwt_expiration_hours()))
         .expect("valid timestamp")
         .timestamp();
 
-    let claims = Claims {
-        sub: username.to_owned(),
+    Claims {
+        sub: subject.to_owned(),
         exp: expiration as usize,
-    };
+        iat: now.timestamp() as usize,
+        jti: Uuid::new_v4().to_string(),
+        roles: Vec::new(),
+        sid: None,
+        client_id: None,
+        scope: None,
+        act: None,
+    }
+}
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(&get_jwt_secret()),
-    )
+fn sign_jwt(claims: &Claims, token_type: &str) -> Result<String, AppError> {
+    let signing = &jwt_keys::keys().signing;
+    let mut header = Header::new(signing.algorithm);
+    header.kid = signing.kid.clone();
+
+    let token = encode(&header, claims, &signing.key)
     .map_err(|e| {
-        println!("Error creating JWT: {}", e);
+        tracing::error!("Error creating JWT: {}", e);
         AppError::DatabaseError(sqlx::Error::Protocol("Error creating JWT".into()))
-    })
+    })?;
+
+    crate::metrics::record_jwt_issued(token_type);
+    Ok(token)
 }
 
 pub fn validate_jwt(token: &str) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(&get_jwt_secret()),
-        &Validation::default(),
-    )
+    // El kid del header decide qué clave pública usar (varias activas durante la rotación)
+    let header = decode_header(token)?;
+    let key = jwt_keys::keys()
+        .verification_key(header.kid.as_deref())
+        .ok_or(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat)?;
+
+    // El algoritmo lo fija la clave, nunca el header (evita ataques de "alg confusion")
+    decode::<Claims>(token, &key.key, &Validation::new(key.algorithm))
+}
+
+// --- Refresh Tokens y sesiones ---
+//
+// Cada refresh token pertenece a una "familia", que es la sesión que nació en
+// el login (su id viaja como `sid` en los access tokens). En cada /refresh se
+// emite un token nuevo de la misma familia y el anterior queda marcado como
+// usado. Si alguien presenta un token ya rotado, asumimos que se ha filtrado y
+// revocamos la familia entera.
+//
+// La familia caduca a los REFRESH_TOKEN_TTL_SECONDS del login, se rote o no:
+// la rotación no alarga la sesión (cada token nuevo vive lo que le quede a la
+// familia). Pasado ese momento hay que volver a hacer login.
+//
+// Claves en Redis:
+//   refresh_token:<token>       -> JSON RefreshTokenData (token vigente)
+//   refresh_token_used:<token>  -> JSON RefreshTokenData (token ya rotado)
+//   refresh_family:<family_id>  -> token vigente de la familia
+//   session:<family_id>         -> JSON SessionData (dispositivo, IP, fechas)
+//   user_sessions:<username>    -> SET de familias del usuario (listar / revocar todo)
+
+pub const REFRESH_TOKEN_TTL_SECONDS: u64 = 7 * 24 * 60 * 60; // 7 días
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshTokenData {
+    pub username: String,
+    pub family_id: String,
+    /// Caducidad absoluta de la familia (fijada en el login)
+    #[serde(default = "family_expiry_from_now")]
+    pub expires_at: DateTime<Utc>,
 }
 
-// --- Refresh Tokens ---
+/// Caducidad de una familia que nace ahora. También es el default de los
+/// tokens guardados antes de existir `expires_at`: caducan como mucho en un TTL
+fn family_expiry_from_now() -> DateTime<Utc> {
+    Utc::now() + Duration::seconds(REFRESH_TOKEN_TTL_SECONDS as i64)
+}
+
+impl RefreshTokenData {
+    /// Segundos que le quedan a la familia (0 si ya ha caducado)
+    fn remaining_seconds(&self) -> u64 {
+        (self.expires_at - Utc::now()).num_seconds().max(0) as u64
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionData {
+    pub username: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_refresh_at: DateTime<Utc>,
+}
+
+/// Resultado de intentar rotar un refresh token
+pub enum RefreshRotation {
+    /// Token válido: se ha emitido uno nuevo de la misma familia
+    Rotated { username: String, session_id: String, refresh_token: String },
+    /// Token ya rotado presentado de nuevo: la familia ha sido revocada
+    Reused,
+    /// Token desconocido o expirado
+    Invalid,
+}
+
+pub async fn redis_connection(redis_client: &redis::Client) -> Result<redis::aio::Connection, AppError> {
+    redis_client.get_async_connection().await.map_err(|e| {
+        tracing::error!("Redis connection error: {}", e);
+        AppError::DatabaseError(sqlx::Error::Protocol("Redis error".into()))
+    })
+}
+
+pub fn redis_command_error(command: &'static str) -> impl Fn(redis::RedisError) -> AppError {
+    move |e| {
+        tracing::error!("Redis {} error: {}", command, e);
+        AppError::DatabaseError(sqlx::Error::Protocol("Redis error".into()))
+    }
+}
+
+fn corrupted_data(e: serde_json::Error) -> AppError {
+    tracing::error!("Corrupted session data in Redis: {}", e);
+    AppError::DatabaseError(sqlx::Error::Protocol("Redis error".into()))
+}
 
 pub fn create_refresh_token() -> String {
     Uuid::new_v4().to_string()
 }
 
+async fn save_refresh_token(
+    conn: &mut redis::aio::Connection,
+    refresh_token: &str,
+    data: &RefreshTokenData,
+    session: &SessionData,
+) -> Result<(), AppError> {
+    let payload = serde_json::to_string(data).expect("RefreshTokenData serializable");
+    let session_payload = serde_json::to_string(session).expect("SessionData serializable");
+    let index_key = format!("user_sessions:{}", data.username);
+    let ttl = data.remaining_seconds().max(1); // SET EX no admite 0
+
+    // El índice vive lo que la familia más reciente del usuario: nunca más de un TTL
+    let _: () = redis::pipe()
+        .atomic()
+        .set_ex(format!("refresh_token:{}", refresh_token), payload, ttl)
+        .set_ex(format!("refresh_family:{}", data.family_id), refresh_token, ttl)
+        .set_ex(format!("session:{}", data.family_id), session_payload, ttl)
+        .sadd(&index_key, &data.family_id)
+        .expire(&index_key, REFRESH_TOKEN_TTL_SECONDS as i64)
+        .query_async(conn)
+        .await
+        .map_err(redis_command_error("SET"))?;
+
+    crate::metrics::record_jwt_issued("refresh");
+    Ok(())
+}
+
+/// Guarda un refresh token recién emitido abriendo una nueva sesión (familia).
+/// Devuelve el id de sesión, que debe ir como `sid` en el access token.
 pub async fn store_refresh_token(
     redis_client: &redis::Client,
     username: &str,
     refresh_token: &str,
-) -> Result<(), AppError> {
-    let mut conn = redis_client
-        .get_async_connection()
+    client: &ClientInfo,
+) -> Result<String, AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+
+    let data = RefreshTokenData {
+        username: username.to_owned(),
+        family_id: Uuid::new_v4().to_string(),
+        expires_at: family_expiry_from_now(),
+    };
+    let now = Utc::now();
+    let session = SessionData {
+        username: username.to_owned(),
+        user_agent: client.user_agent.clone(),
+        ip: client.ip.clone(),
+        created_at: now,
+        last_refresh_at: now,
+    };
+
+    save_refresh_token(&mut conn, refresh_token, &data, &session).await?;
+    Ok(data.family_id)
+}
+
+/// Consume un refresh token y emite el siguiente de su familia.
+///
+/// RENAME retira el token y lo deja marcado como usado en un solo paso atómico
+/// (conservando su TTL, lo que le queda a la familia): una segunda petición con
+/// el mismo token, llegue cuando llegue, lo verá como reutilizado.
+pub async fn rotate_refresh_token(
+    redis_client: &redis::Client,
+    refresh_token: &str,
+    client: &ClientInfo,
+) -> Result<RefreshRotation, AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+
+    let token_key = format!("refresh_token:{}", refresh_token);
+    let used_key = format!("refresh_token_used:{}", refresh_token);
+
+    let consumed = match conn.rename::<_, _, ()>(&token_key, &used_key).await {
+        Ok(()) => true,
+        Err(e) if e.detail() == Some("no such key") => false,
+        Err(e) => return Err(redis_command_error("RENAME")(e)),
+    };
+
+    let marker: Option<String> = conn.get(&used_key).await.map_err(redis_command_error("GET"))?;
+    let Some(marker) = marker else {
+        return Ok(RefreshRotation::Invalid);
+    };
+
+    if !consumed {
+        // Los marcadores anteriores a RENAME guardaban solo el family_id
+        let family_id = serde_json::from_str::<RefreshTokenData>(&marker)
+            .map(|data| data.family_id)
+            .unwrap_or(marker);
+        tracing::warn!(
+            security_event = "refresh_token_reuse",
+            family_id = %family_id,
+            "Refresh token reutilizado, revocando la familia completa"
+        );
+        revoke_refresh_family(&mut conn, &family_id).await?;
+        return Ok(RefreshRotation::Reused);
+    }
+
+    let data: RefreshTokenData = serde_json::from_str(&marker).map_err(corrupted_data)?;
+
+    // Familia caducada: la rotación no puede prolongarla (el token ya quedó marcado)
+    if data.remaining_seconds() == 0 {
+        revoke_refresh_family(&mut conn, &data.family_id).await?;
+        return Ok(RefreshRotation::Invalid);
+    }
+
+    // La sesión conserva su origen pero refleja el último uso
+    let stored: Option<String> = conn
+        .get(format!("session:{}", data.family_id))
         .await
-        .map_err(|e| {
-            tracing::error!("Redis connection error: {}", e);
-            AppError::DatabaseError(sqlx::Error::Protocol("Redis error".into()))
-        })?;
+        .map_err(redis_command_error("GET"))?;
+    let now = Utc::now();
+    let session = match stored {
+        Some(json) => SessionData {
+            last_refresh_at: now,
+            ip: client.ip.clone(),
+            ..serde_json::from_str(&json).map_err(corrupted_data)?
+        },
+        None => SessionData {
+            username: data.username.clone(),
+            user_agent: client.user_agent.clone(),
+            ip: client.ip.clone(),
+            created_at: now,
+            last_refresh_at: now,
+        },
+    };
 
-    let key = format!("refresh_token:{}", refresh_token);
-    let ttl_seconds = 7 * 24 * 60 * 60; // 7 días
+    let new_token = create_refresh_token();
+    save_refresh_token(&mut conn, &new_token, &data, &session).await?;
 
-    let _: () = conn.set_ex(&key, username, ttl_seconds).await.map_err(|e| {
-        tracing::error!("Redis SET error: {}", e);
-        AppError::DatabaseError(sqlx::Error::Protocol("Redis error".into()))
-    })?;
+    Ok(RefreshRotation::Rotated {
+        username: data.username,
+        session_id: data.family_id,
+        refresh_token: new_token,
+    })
+}
+
+async fn revoke_refresh_family(
+    conn: &mut redis::aio::Connection,
+    family_id: &str,
+) -> Result<(), AppError> {
+    let family_key = format!("refresh_family:{}", family_id);
+    let session_key = format!("session:{}", family_id);
+
+    let current: Option<String> = conn.get(&family_key).await.map_err(redis_command_error("GET"))?;
+    let session: Option<String> = conn.get(&session_key).await.map_err(redis_command_error("GET"))?;
+
+    let mut keys = vec![family_key, session_key];
+    if let Some(token) = current {
+        keys.push(format!("refresh_token:{}", token));
+    }
+
+    let _: () = conn.del(keys).await.map_err(redis_command_error("DEL"))?;
+
+    if let Some(session) = session.and_then(|json| serde_json::from_str::<SessionData>(&json).ok()) {
+        let _: () = conn
+            .srem(format!("user_sessions:{}", session.username), family_id)
+            .await
+            .map_err(redis_command_error("SREM"))?;
+    }
 
     Ok(())
 }
 
-pub async fn validate_refresh_token(
+/// Revoca la familia del refresh token. Con `owner`, solo si el token es de ese
+/// usuario. Devuelve false si el token no existe o es de otro.
+pub async fn revoke_refresh_token(
     redis_client: &redis::Client,
     refresh_token: &str,
-) -> Result<Option<String>, AppError> {
-    let mut conn = redis_client
-        .get_async_connection()
+    owner: Option<&str>,
+) -> Result<bool, AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+
+    let current: Option<String> = conn
+        .get(format!("refresh_token:{}", refresh_token))
         .await
-        .map_err(|e| {
-            tracing::error!("Redis connection error: {}", e);
-            AppError::DatabaseError(sqlx::Error::Protocol("Redis error".into()))
-        })?;
-
-    let key = format!("refresh_token:{}", refresh_token);
-    let username: Option<String> = conn.get(&key).await.map_err(|e| {
-        tracing::error!("Redis GET error: {}", e);
-        AppError::DatabaseError(sqlx::Error::Protocol("Redis error".into()))
-    })?;
+        .map_err(redis_command_error("GET"))?;
+
+    let Some(data) = current.and_then(|current| serde_json::from_str::<RefreshTokenData>(&current).ok()) else {
+        return Ok(false);
+    };
+    if owner.is_some_and(|owner| owner != data.username) {
+        return Ok(false);
+    }
 
-    Ok(username)
+    let _: () = conn
+        .del(format!("refresh_token:{}", refresh_token))
+        .await
+        .map_err(redis_command_error("DEL"))?;
+    revoke_refresh_family(&mut conn, &data.family_id).await?;
+    Ok(true)
 }
 
-pub async fn revoke_refresh_token(
+/// Sesiones activas del usuario: (id, datos). Limpia del índice las ya expiradas.
+pub async fn list_sessions(
     redis_client: &redis::Client,
-    refresh_token: &str,
-) -> Result<(), AppError> {
-    let mut conn = redis_client
-        .get_async_connection()
+    username: &str,
+) -> Result<Vec<(String, SessionData)>, AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+    let index_key = format!("user_sessions:{}", username);
+
+    let session_ids: Vec<String> = conn.smembers(&index_key).await.map_err(redis_command_error("SMEMBERS"))?;
+
+    let mut sessions = Vec::with_capacity(session_ids.len());
+    for session_id in session_ids {
+        let stored: Option<String> = conn
+            .get(format!("session:{}", session_id))
+            .await
+            .map_err(redis_command_error("GET"))?;
+
+        match stored {
+            Some(json) => sessions.push((session_id, serde_json::from_str(&json).map_err(corrupted_data)?)),
+            None => {
+                let _: () = conn.srem(&index_key, &session_id).await.map_err(redis_command_error("SREM"))?;
+            }
+        }
+    }
+
+    sessions.sort_by_key(|(_, session): &(String, SessionData)| std::cmp::Reverse(session.last_refresh_at));
+    Ok(sessions)
+}
+
+/// Revoca una sesión concreta del usuario. Devuelve false si no existe o no es suya.
+pub async fn revoke_session(
+    redis_client: &redis::Client,
+    username: &str,
+    session_id: &str,
+) -> Result<bool, AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+
+    let is_member: bool = conn
+        .sismember(format!("user_sessions:{}", username), session_id)
         .await
-        .map_err(|e| {
-            tracing::error!("Redis connection error: {}", e);
-            AppError::DatabaseError(sqlx::Error::Protocol("Redis error".into()))
-        })?;
-
-    let key = format!("refresh_token:{}", refresh_token);
-    let _: () = conn.del(&key).await.map_err(|e| {
-        tracing::error!("Redis DEL error: {}", e);
-        AppError::DatabaseError(sqlx::Error::Protocol("Redis error".into()))
-    })?;
+        .map_err(redis_command_error("SISMEMBER"))?;
+
+    if !is_member {
+        return Ok(false);
+    }
+
+    revoke_refresh_family(&mut conn, session_id).await?;
+    Ok(true)
+}
+
+/// Revoca todas las sesiones de un usuario ("cerrar sesión en todas partes", reset de contraseña...)
+pub async fn revoke_all_sessions(
+    redis_client: &redis::Client,
+    username: &str,
+) -> Result<(), AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+    let index_key = format!("user_sessions:{}", username);
 
+    let families: Vec<String> = conn.smembers(&index_key).await.map_err(redis_command_error("SMEMBERS"))?;
+    for family_id in &families {
+        revoke_refresh_family(&mut conn, family_id).await?;
+    }
+
+    let _: () = conn.del(&index_key).await.map_err(redis_command_error("DEL"))?;
+    tracing::info!("Revocadas {} sesiones de {}", families.len(), username);
     Ok(())
 }
+
+/// Revoca todas las sesiones del usuario salvo `keep` (cambio de contraseña desde
+/// una sesión que debe seguir abierta). Devuelve cuántas se han revocado.
+pub async fn revoke_other_sessions(
+    redis_client: &redis::Client,
+    username: &str,
+    keep: &str,
+) -> Result<usize, AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+    let index_key = format!("user_sessions:{}", username);
+
+    let families: Vec<String> = conn.smembers(&index_key).await.map_err(redis_command_error("SMEMBERS"))?;
+    let others: Vec<&String> = families.iter().filter(|family_id| *family_id != keep).collect();
+    for family_id in &others {
+        revoke_refresh_family(&mut conn, family_id).await?;
+        let _: () = conn.srem(&index_key, *family_id).await.map_err(redis_command_error("SREM"))?;
+    }
+
+    tracing::info!("Revocadas {} sesiones de {} (se conserva la actual)", others.len(), username);
+    Ok(others.len())
+}
+
+// --- Tokens opacos de un solo uso (reset de contraseña, enlaces por email...) ---
+
+/// Genera un token aleatorio (base64url, 256 bits) y su hash SHA-256 para guardar en BBDD
+pub fn create_opaque_token() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+    let hash = hash_opaque_token(&token);
+    (token, hash)
+}
+
+pub fn hash_opaque_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+// --- Verificación de email ---
+//
+// Token firmado (HMAC-SHA256) y autocontenido: base64url(user_id:email:exp).base64url(firma)
+// Incluye el email, así que si el usuario lo cambia los enlaces antiguos dejan de valer.
+
+// Los secretos de los enlaces son obligatorios y propios: con RS256/EdDSA no
+// hay JWT_SECRET del que tirar, y un literal por defecto permitiría a cualquiera
+// fabricar enlaces. `check_link_secrets()` hace que la API no arranque sin ellos.
+
+fn required_secret(name: &str) -> Vec<u8> {
+    match env::var(name) {
+        Ok(secret) if !secret.trim().is_empty() => secret.into_bytes(),
+        _ => panic!("{} no está definido: es obligatorio para firmar los enlaces enviados por email", name),
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref EMAIL_VERIFICATION_SECRET: Vec<u8> = required_secret("EMAIL_VERIFICATION_SECRET");
+    static ref MAGIC_LINK_SECRET: Vec<u8> = required_secret("MAGIC_LINK_SECRET");
+}
+
+/// Falla rápido al arrancar si falta algún secreto de firma de enlaces
+pub fn check_link_secrets() {
+    lazy_static::initialize(&EMAIL_VERIFICATION_SECRET);
+    lazy_static::initialize(&MAGIC_LINK_SECRET);
+}
+
+fn hmac_signature(secret: &[u8], payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC acepta claves de cualquier tamaño");
+    mac.update(payload.as_bytes());
+    URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+/// Payload decodificado si la firma es correcta (comparación en tiempo constante)
+fn verify_hmac_signature(secret: &[u8], token: &str) -> Option<String> {
+    let (payload, signature) = token.split_once('.')?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).ok()?;
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?).ok()?;
+
+    String::from_utf8(URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+}
+
+pub fn create_email_verification_token(user_id: i32, email: &str) -> String {
+    let ttl_hours: i64 = env::var("EMAIL_VERIFICATION_TTL_HOURS")
+        .unwrap_or_else(|_| "24".to_string())
+        .parse()
+        .unwrap_or(24);
+    let expires = (Utc::now() + Duration::hours(ttl_hours)).timestamp();
+
+    let payload = URL_SAFE_NO_PAD.encode(format!("{}:{}:{}", user_id, expires, email));
+    let signature = hmac_signature(&EMAIL_VERIFICATION_SECRET, &payload);
+    format!("{}.{}", payload, signature)
+}
+
+/// Devuelve (user_id, email) si la firma es correcta y el token no ha caducado
+pub fn validate_email_verification_token(token: &str) -> Option<(i32, String)> {
+    let decoded = verify_hmac_signature(&EMAIL_VERIFICATION_SECRET, token)?;
+    let mut parts = decoded.splitn(3, ':');
+    let user_id: i32 = parts.next()?.parse().ok()?;
+    let expires: i64 = parts.next()?.parse().ok()?;
+    let email = parts.next()?.to_string();
+
+    (expires > Utc::now().timestamp()).then_some((user_id, email))
+}
+
+pub fn email_verification_required() -> bool {
+    env::var("REQUIRE_EMAIL_VERIFICATION")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false)
+}
+
+// --- Magic links (login sin contraseña) ---
+//
+// Mismo formato firmado que la verificación de email: base64url(user_id:exp:nonce:email).firma
+// La firma impide fabricar enlaces; el nonce en Redis los hace de un solo uso.
+// Como lleva el email, cambiarlo invalida los enlaces pendientes.
+// El enlace abre una página (GET, no consume nada) y el canje es un POST: los
+// escáneres de correo que siguen enlaces no gastan el token.
+
+pub fn magic_link_ttl_minutes() -> i64 {
+    env::var("MAGIC_LINK_TTL_MINUTES")
+        .unwrap_or_else(|_| "15".to_string())
+        .parse()
+        .unwrap_or(15)
+}
+
+pub async fn create_magic_link_token(
+    redis_client: &redis::Client,
+    user_id: i32,
+    email: &str,
+) -> Result<String, AppError> {
+    let ttl_minutes = magic_link_ttl_minutes();
+    let expires = (Utc::now() + Duration::minutes(ttl_minutes)).timestamp();
+    let nonce = Uuid::new_v4().simple().to_string();
+
+    let mut conn = redis_connection(redis_client).await?;
+    let _: () = conn
+        .set_ex(format!("magic_link:{}", nonce), user_id, (ttl_minutes * 60) as u64)
+        .await
+        .map_err(redis_command_error("SET"))?;
+
+    let payload = URL_SAFE_NO_PAD.encode(format!("{}:{}:{}:{}", user_id, expires, nonce, email));
+    let signature = hmac_signature(&MAGIC_LINK_SECRET, &payload);
+    Ok(format!("{}.{}", payload, signature))
+}
+
+/// Valida y consume el enlace. Devuelve (user_id, email) la primera vez que se usa.
+pub async fn consume_magic_link_token(
+    redis_client: &redis::Client,
+    token: &str,
+) -> Result<(i32, String), AppError> {
+    let parsed = verify_hmac_signature(&MAGIC_LINK_SECRET, token).and_then(|decoded| {
+        let mut parts = decoded.splitn(4, ':');
+        let user_id: i32 = parts.next()?.parse().ok()?;
+        let expires: i64 = parts.next()?.parse().ok()?;
+        let nonce = parts.next()?.to_string();
+        let email = parts.next()?.to_string();
+        Some((user_id, expires, nonce, email))
+    });
+
+    let Some((user_id, expires, nonce, email)) = parsed else {
+        return Err(AppError::AuthError("Invalid login link".to_string()));
+    };
+    if expires <= Utc::now().timestamp() {
+        return Err(AppError::AuthError("This login link has expired. Request a new one.".to_string()));
+    }
+
+    // GETDEL atómico: de dos peticiones con el mismo enlace solo una lo consigue
+    let mut conn = redis_connection(redis_client).await?;
+    let stored: Option<i32> = conn
+        .get_del(format!("magic_link:{}", nonce))
+        .await
+        .map_err(redis_command_error("GETDEL"))?;
+
+    match stored {
+        Some(stored) if stored == user_id => Ok((user_id, email)),
+        _ => Err(AppError::AuthError("This login link has already been used. Request a new one.".to_string())),
+    }
+}
+
+// --- MFA pendiente ---
+//
+// Tras una contraseña correcta, un usuario con TOTP recibe un token opaco de
+// corta duración que SOLO sirve para /login/mfa. No es un JWT: no puede usarse
+// contra ninguna ruta protegida.
+
+const MFA_PENDING_TTL_SECONDS: u64 = 5 * 60;
+
+pub async fn create_mfa_pending_token(
+    redis_client: &redis::Client,
+    username: &str,
+) -> Result<String, AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+    let token = Uuid::new_v4().to_string();
+
+    let _: () = conn
+        .set_ex(format!("mfa_pending:{}", token), username, MFA_PENDING_TTL_SECONDS)
+        .await
+        .map_err(redis_command_error("SET"))?;
+
+    Ok(token)
+}
+
+pub async fn get_mfa_pending_user(
+    redis_client: &redis::Client,
+    token: &str,
+) -> Result<Option<String>, AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+    conn.get(format!("mfa_pending:{}", token))
+        .await
+        .map_err(redis_command_error("GET"))
+}
+
+/// Consume el token pendiente. Devuelve false si ya se había usado (petición concurrente).
+pub async fn consume_mfa_pending_token(
+    redis_client: &redis::Client,
+    token: &str,
+) -> Result<bool, AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+    let username: Option<String> = conn
+        .get_del(format!("mfa_pending:{}", token))
+        .await
+        .map_err(redis_command_error("GETDEL"))?;
+    Ok(username.is_some())
+}
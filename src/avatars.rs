@@ -0,0 +1,115 @@
+// ============================================================================
+// AVATARES
+// ============================================================================
+//
+// POST /me/avatar (multipart, campo "avatar"):
+//   1. Límite de tamaño (AVATAR_MAX_BYTES, default 5 MiB).
+//   2. El tipo se deduce de los bytes (magic numbers), nunca del Content-Type
+//      ni de la extensión que manda el cliente: PNG, JPEG, WebP o GIF.
+//   3. Se decodifica con límites de dimensiones (contra "bombas" de
+//      descompresión) y se recorta/escala a miniaturas cuadradas fijas.
+//   4. Las miniaturas se guardan como PNG en el storage:
+//        avatars/<user_id>/<uuid>/{64,128,256}.png
+//      Cada subida usa un uuid nuevo, así que las URLs se pueden cachear
+//      para siempre. avatar_url apunta a la de 256; las demás comparten ruta.
+//
+// Volver a codificar la imagen también descarta los metadatos (EXIF, GPS...).
+//
+// ============================================================================
+
+use image::{imageops::FilterType, ImageFormat, ImageReader, Limits};
+use std::{env, io::Cursor};
+use crate::{builders::FieldError, error::AppError, storage::Storage};
+
+/// Lado de cada miniatura, en píxeles. La última es la que expone avatar_url.
+pub const THUMBNAIL_SIZES: [u32; 3] = [64, 128, 256];
+
+/// Dimensiones máximas de la imagen original
+const MAX_DIMENSION: u32 = 4096;
+
+/// Memoria máxima del decodificador: la imagen más grande admitida en RGBA de 8 bits
+const MAX_DECODE_BYTES: u64 = MAX_DIMENSION as u64 * MAX_DIMENSION as u64 * 4;
+
+pub fn max_upload_bytes() -> usize {
+    env::var("AVATAR_MAX_BYTES")
+        .unwrap_or_else(|_| (5 * 1024 * 1024).to_string())
+        .parse()
+        .unwrap_or(5 * 1024 * 1024)
+}
+
+fn rejected(code: &str, message: &str) -> AppError {
+    AppError::Validation(vec![FieldError::new("avatar", code, message)])
+}
+
+/// Valida la imagen y genera las miniaturas PNG (trabajo de CPU: fuera del runtime async)
+pub async fn make_thumbnails(bytes: Vec<u8>) -> Result<Vec<(u32, Vec<u8>)>, AppError> {
+    if bytes.len() > max_upload_bytes() {
+        return Err(rejected("too_large", &format!("Must be at most {} bytes", max_upload_bytes())));
+    }
+
+    match image::guess_format(&bytes) {
+        Ok(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Gif) => {}
+        _ => return Err(rejected("unsupported_type", "Must be a PNG, JPEG, WebP or GIF image")),
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let mut reader = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .map_err(|_| rejected("invalid_image", "Could not read the image"))?;
+
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_DIMENSION);
+        limits.max_image_height = Some(MAX_DIMENSION);
+        limits.max_alloc = Some(MAX_DECODE_BYTES);
+        reader.limits(limits);
+
+        let image = reader
+            .decode()
+            .map_err(|_| rejected("invalid_image", "Could not decode the image or it is too large"))?;
+
+        THUMBNAIL_SIZES
+            .iter()
+            .map(|&size| {
+                let mut png = Vec::new();
+                image
+                    .resize_to_fill(size, size, FilterType::Lanczos3)
+                    .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                    .map_err(|e| {
+                        tracing::error!("No se pudo codificar la miniatura de {}px: {}", size, e);
+                        AppError::DatabaseError(sqlx::Error::Protocol("Image encoding error".into()))
+                    })?;
+                Ok((size, png))
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!("Tarea de miniaturas abortada: {}", e);
+        AppError::DatabaseError(sqlx::Error::Protocol("Image processing error".into()))
+    })?
+}
+
+/// Guarda las miniaturas bajo un prefijo nuevo. Devuelve (prefijo, URL de la grande).
+pub async fn store_thumbnails(
+    storage: &dyn Storage,
+    user_id: i32,
+    thumbnails: Vec<(u32, Vec<u8>)>,
+) -> Result<(String, String), AppError> {
+    let prefix = format!("avatars/{}/{}", user_id, uuid::Uuid::new_v4().simple());
+
+    let mut url = String::new();
+    for (size, png) in thumbnails {
+        let key = format!("{}/{}.png", prefix, size);
+        storage.put(&key, png).await?;
+        url = storage.public_url(&key);
+    }
+
+    Ok((prefix, url))
+}
+
+/// Borra los ficheros de un avatar anterior. Un fallo aquí no debe tumbar la petición.
+pub async fn delete_quietly(storage: &dyn Storage, prefix: &str) {
+    if let Err(e) = storage.delete_prefix(prefix).await {
+        tracing::warn!("No se pudo borrar el avatar {}: {:?}", prefix, e);
+    }
+}
@@ -1,4 +1,7 @@
 // Módulo que exporta todos los builders con Type-State Pattern
 pub mod user_builder;
+pub mod password_policy;
+pub mod profile;
 
 pub use user_builder::UserRegistration;
+pub use password_policy::FieldError;
@@ -0,0 +1,126 @@
+// ============================================================================
+// POLÍTICA DE CONTRASEÑAS
+// ============================================================================
+//
+// El Type-State de UserRegistration garantiza en COMPILACIÓN que hay password.
+// Lo que no puede garantizar el compilador es que sea BUENA: eso se valida aquí
+// en runtime y se devuelven errores por campo (422), nunca un panic ni un 500.
+//
+// Configuración:
+//   PASSWORD_MIN_LENGTH         (default 10)
+//   PASSWORD_REQUIRE_LOWERCASE  (default true)
+//   PASSWORD_REQUIRE_UPPERCASE  (default true)
+//   PASSWORD_REQUIRE_DIGIT      (default true)
+//   PASSWORD_REQUIRE_SYMBOL     (default false)
+//   COMMON_PASSWORDS_FILE       (default config/common_passwords.txt, una por línea)
+//
+// ============================================================================
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{collections::HashSet, env};
+
+/// Error de validación asociado a un campo del request
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &str, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    common_passwords: HashSet<String>,
+}
+
+lazy_static! {
+    static ref POLICY: PasswordPolicy = PasswordPolicy::from_env();
+}
+
+pub fn policy() -> &'static PasswordPolicy {
+    &POLICY
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    env::var(name).map(|v| v == "true" || v == "1").unwrap_or(default)
+}
+
+impl PasswordPolicy {
+    fn from_env() -> Self {
+        let path = env::var("COMMON_PASSWORDS_FILE")
+            .unwrap_or_else(|_| "config/common_passwords.txt".to_string());
+
+        let common_passwords = match std::fs::read_to_string(&path) {
+            Ok(content) => content
+                .lines()
+                .map(|line| line.trim().to_lowercase())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .collect(),
+            Err(e) => {
+                tracing::warn!("No se pudo leer la lista de contraseñas comunes ({}): {}", path, e);
+                HashSet::new()
+            }
+        };
+
+        PasswordPolicy {
+            min_length: env::var("PASSWORD_MIN_LENGTH").ok().and_then(|v| v.parse().ok()).unwrap_or(10),
+            require_lowercase: env_flag("PASSWORD_REQUIRE_LOWERCASE", true),
+            require_uppercase: env_flag("PASSWORD_REQUIRE_UPPERCASE", true),
+            require_digit: env_flag("PASSWORD_REQUIRE_DIGIT", true),
+            require_symbol: env_flag("PASSWORD_REQUIRE_SYMBOL", false),
+            common_passwords,
+        }
+    }
+
+    /// Devuelve TODOS los incumplimientos (no solo el primero) para el campo `field`
+    pub fn validate(&self, field: &str, username: &str, password: &str) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            errors.push(FieldError::new(
+                field,
+                "too_short",
+                format!("Must be at least {} characters long", self.min_length),
+            ));
+        }
+
+        let classes = [
+            (self.require_lowercase, password.chars().any(|c| c.is_lowercase()), "missing_lowercase", "a lowercase letter"),
+            (self.require_uppercase, password.chars().any(|c| c.is_uppercase()), "missing_uppercase", "an uppercase letter"),
+            (self.require_digit, password.chars().any(|c| c.is_ascii_digit()), "missing_digit", "a digit"),
+            (self.require_symbol, password.chars().any(|c| !c.is_alphanumeric()), "missing_symbol", "a symbol"),
+        ];
+        for (required, present, code, description) in classes {
+            if required && !present {
+                errors.push(FieldError::new(field, code, format!("Must contain {}", description)));
+            }
+        }
+
+        let lowered = password.to_lowercase();
+        // Usernames muy cortos ("al") darían falsos positivos con `contains`
+        let username = username.trim().to_lowercase();
+        if username.chars().count() >= 3 && lowered.contains(&username) {
+            errors.push(FieldError::new(field, "contains_username", "Must not contain the username"));
+        }
+
+        if self.common_passwords.contains(&lowered) {
+            errors.push(FieldError::new(field, "too_common", "This password is too common"));
+        }
+
+        errors
+    }
+}
@@ -0,0 +1,99 @@
+// ============================================================================
+// VALIDACIÓN DEL PERFIL (PATCH /users/:id)
+// ============================================================================
+//
+// Convierte el body del PATCH en los cambios a aplicar:
+//   - Campo ausente  -> no se toca
+//   - Cadena vacía   -> se borra (salvo email, que es obligatorio)
+//   - Valor          -> se valida y se guarda recortado
+//
+// Todos los errores se devuelven juntos, por campo (422), igual que el registro.
+//
+// ============================================================================
+
+use crate::{builders::{user_builder::is_plausible_email, FieldError}, models::UpdateUserRequest};
+
+const MAX_DISPLAY_NAME: usize = 100;
+const MAX_BIO: usize = 500;
+
+/// Cambios validados. `Some(None)` = borrar el campo.
+#[derive(Debug, Default)]
+pub struct ProfileUpdate {
+    pub email: Option<String>,
+    pub display_name: Option<Option<String>>,
+    pub bio: Option<Option<String>>,
+    pub timezone: Option<Option<String>>,
+    pub locale: Option<Option<String>>,
+}
+
+pub fn validate(request: &UpdateUserRequest) -> Result<ProfileUpdate, Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    let email = request.email.as_deref().map(str::trim);
+    if email.is_some_and(|e| !is_plausible_email(e)) {
+        errors.push(FieldError::new("email", "invalid", "Must be a valid email address"));
+    }
+
+    let display_name = clearable(request.display_name.as_deref());
+    if display_name.flatten().is_some_and(|n| n.chars().count() > MAX_DISPLAY_NAME) {
+        errors.push(FieldError::new(
+            "display_name",
+            "too_long",
+            format!("Must be at most {} characters long", MAX_DISPLAY_NAME),
+        ));
+    }
+
+    let bio = clearable(request.bio.as_deref());
+    if bio.flatten().is_some_and(|b| b.chars().count() > MAX_BIO) {
+        errors.push(FieldError::new("bio", "too_long", format!("Must be at most {} characters long", MAX_BIO)));
+    }
+
+    let timezone = clearable(request.timezone.as_deref());
+    if timezone.flatten().is_some_and(|tz| !is_plausible_timezone(tz)) {
+        errors.push(FieldError::new("timezone", "invalid", "Must be an IANA time zone like Europe/Madrid or UTC"));
+    }
+
+    let locale = clearable(request.locale.as_deref());
+    if locale.flatten().is_some_and(|l| !is_plausible_locale(l)) {
+        errors.push(FieldError::new("locale", "invalid", "Must be a language tag like es or es-ES"));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let owned = |value: Option<Option<&str>>| value.map(|v| v.map(str::to_string));
+    Ok(ProfileUpdate {
+        email: email.map(str::to_string),
+        display_name: owned(display_name),
+        bio: owned(bio),
+        timezone: owned(timezone),
+        locale: owned(locale),
+    })
+}
+
+/// Ausente -> None; "" -> Some(None) (borrar); valor -> Some(Some(recortado))
+fn clearable(value: Option<&str>) -> Option<Option<&str>> {
+    value.map(|v| Some(v.trim()).filter(|v| !v.is_empty()))
+}
+
+/// Forma de un nombre IANA (Area/Ciudad o UTC). No comprobamos que exista en la base tz.
+fn is_plausible_timezone(timezone: &str) -> bool {
+    let valid_chars = timezone
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'));
+    valid_chars
+        && timezone.len() <= 64
+        && (timezone == "UTC" || (timezone.contains('/') && !timezone.starts_with('/') && !timezone.ends_with('/')))
+}
+
+/// BCP 47 simplificado: idioma de 2-3 letras y subtags alfanuméricos opcionales
+fn is_plausible_locale(locale: &str) -> bool {
+    let mut parts = locale.split(['-', '_']);
+    let language_ok = parts
+        .next()
+        .is_some_and(|l| (2..=3).contains(&l.len()) && l.chars().all(|c| c.is_ascii_alphabetic()));
+    language_ok
+        && locale.len() <= 35
+        && parts.all(|p| (1..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric()))
+}
@@ -19,6 +19,7 @@
 // ============================================================================
 
 use std::marker::PhantomData;
+use super::password_policy::{self, FieldError};
 
 // ----------------------------------------------------------------------------
 // ESTADOS (Tipos vacíos que solo existen en compile-time)
@@ -82,7 +83,7 @@ impl UserRegistration<NoUsername> {
 // ----------------------------------------------------------------------------
 // IMPLEMENTACIÓN: Estado NoPassword
 // ----------------------------------------------------------------------------
-// Solo este estado tiene .password() y .email()
+// Solo este estado tiene .password()
 
 impl UserRegistration<NoPassword> {
     /// Configura el password y CAMBIA EL TIPO a Ready
@@ -94,16 +95,6 @@ impl UserRegistration<NoPassword> {
             _state: PhantomData,
         }
     }
-
-    /// Configura email (opcional) sin cambiar el estado
-    pub fn email(self, email: impl Into<String>) -> Self {
-        UserRegistration {
-            username: self.username,
-            password: self.password,
-            email: Some(email.into()),
-            _state: PhantomData,
-        }
-    }
 }
 
 // ----------------------------------------------------------------------------
@@ -112,7 +103,7 @@ impl UserRegistration<NoPassword> {
 // SOLO este estado tiene .build() - garantiza que username y password existen
 
 impl UserRegistration<Ready> {
-    /// Email opcional (se puede configurar antes de .password())
+    /// Email opcional
     pub fn email(self, email: impl Into<String>) -> Self {
         UserRegistration {
             username: self.username,
@@ -125,13 +116,44 @@ impl UserRegistration<Ready> {
     /// Construye los datos finales
     /// 
     /// GARANTÍA DEL COMPILADOR: username y password SIEMPRE existen aquí
-    /// No necesitamos .unwrap() peligroso ni validaciones runtime
-    pub fn build(self) -> (String, String, Option<String>) {
-        (
-            self.username.unwrap(), // Safe: garantizado por el tipo Ready
-            self.password.unwrap(), // Safe: garantizado por el tipo Ready
-            self.email,
-        )
+    /// No necesitamos .unwrap() peligroso
+    ///
+    /// Lo que sí se valida en runtime es el CONTENIDO: la política de
+    /// contraseñas y el formato del email. Se devuelven todos los errores
+    /// por campo a la vez para que el cliente pueda mostrarlos juntos.
+    pub fn build(self) -> Result<(String, String, Option<String>), Vec<FieldError>> {
+        let username = self.username.unwrap(); // Safe: garantizado por el tipo Ready
+        let password = self.password.unwrap(); // Safe: garantizado por el tipo Ready
+
+        let mut errors = Vec::new();
+
+        if username.trim().is_empty() {
+            errors.push(FieldError::new("username", "required", "Username is required"));
+        }
+
+        errors.extend(password_policy::policy().validate("password", &username, &password));
+
+        if let Some(email) = &self.email {
+            if !is_plausible_email(email) {
+                errors.push(FieldError::new("email", "invalid", "Must be a valid email address"));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok((username, password, self.email))
+    }
+}
+
+/// Validación mínima: algo@dominio.tld (la verificación real es el enlace por email)
+pub fn is_plausible_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.')
+        }
+        None => false,
     }
 }
 
@@ -143,9 +165,9 @@ impl UserRegistration<Ready> {
 //
 //   let (username, password, email) = UserRegistration::new()
 //       .username("admin")
-//       .password("secret123")
+//       .password("Correct-Horse-42")
 //       .email("admin@example.com")  // Opcional
-//       .build();
+//       .build()?;  // Err(Vec<FieldError>) si la política no se cumple
 //
 // ❌ INCORRECTO (NO compila):
 //
@@ -6,7 +6,7 @@ fn redis_error(e: redis::RedisError) -> AppError {
     // Para simplificar, asumimos que cualquier error de redis es un error "interno" 
     // Podrías crear AppError::CacheError(e)
     println!("Redis Error: {}", e);
-    AppError::DatabaseError(sqlx::Error::Protocol(format!("Redis Error: {}", e).into())) 
+    AppError::DatabaseError(sqlx::Error::Protocol(format!("Redis Error: {}", e))) 
 }
 
 pub async fn get_dashboard_data(client: &redis::Client) -> Result<Option<DashboardData>, AppError> {
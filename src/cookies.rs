@@ -0,0 +1,131 @@
+// ============================================================================
+// MODO COOKIE PARA NAVEGADORES + PROTECCIÓN CSRF
+// ============================================================================
+//
+// Por defecto /login devuelve los tokens en el JSON y el cliente los manda en
+// `Authorization: Bearer`. Un frontend web tendría que guardarlos en storage
+// legible por JavaScript (XSS = robo de tokens). Con la cabecera
+// `X-Session-Mode: cookie` los endpoints que emiten tokens los devuelven en
+// cookies HttpOnly:
+//
+//   refresh_token  HttpOnly, Path=/refresh (solo viaja al renovar)
+//   access_token   HttpOnly, Path=/        (lo lee auth_middleware)
+//   csrf_token     legible por JS, Path=/  (double-submit)
+//
+// Como el navegador manda las cookies solo, las peticiones que cambian estado
+// (POST, PUT, PATCH, DELETE) autenticadas por cookie deben repetir el valor de
+// `csrf_token` en la cabecera `X-CSRF-Token`. Otra web puede provocar la
+// petición, pero no leer nuestra cookie para copiarla en la cabecera.
+// Los clientes con `Authorization: Bearer` no usan cookies y no necesitan CSRF.
+//
+// Configuración:
+//   AUTH_COOKIE_SECURE     Default: true (false solo para desarrollo en http://)
+//   AUTH_COOKIE_SAMESITE   Strict (default) | Lax | None
+//   AUTH_COOKIE_DOMAIN     Opcional (por defecto, solo el host de la API)
+//
+// ============================================================================
+
+use axum::http::{HeaderMap, Method};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use lazy_static::lazy_static;
+use rand::RngCore;
+use std::env;
+use crate::{auth, models::LoginResponse};
+
+pub const ACCESS_COOKIE: &str = "access_token";
+pub const REFRESH_COOKIE: &str = "refresh_token";
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const OIDC_STATE_COOKIE: &str = "oidc_state";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+pub const SESSION_MODE_HEADER: &str = "x-session-mode";
+
+struct CookieConfig {
+    secure: bool,
+    same_site: SameSite,
+    domain: Option<String>,
+}
+
+lazy_static! {
+    static ref CONFIG: CookieConfig = CookieConfig {
+        secure: env::var("AUTH_COOKIE_SECURE").map(|v| v != "false" && v != "0").unwrap_or(true),
+        same_site: match env::var("AUTH_COOKIE_SAMESITE").unwrap_or_default().to_lowercase().as_str() {
+            "lax" => SameSite::Lax,
+            "none" => SameSite::None,
+            _ => SameSite::Strict,
+        },
+        domain: env::var("AUTH_COOKIE_DOMAIN").ok().filter(|d| !d.is_empty()),
+    };
+}
+
+fn build_cookie(name: &'static str, value: String, path: &'static str, http_only: bool, max_age: i64) -> Cookie<'static> {
+    let mut cookie = Cookie::build((name, value))
+        .path(path)
+        .http_only(http_only)
+        .secure(CONFIG.secure)
+        .same_site(CONFIG.same_site)
+        .max_age(time::Duration::seconds(max_age))
+        .build();
+    if let Some(domain) = &CONFIG.domain {
+        cookie.set_domain(domain.clone());
+    }
+    cookie
+}
+
+pub fn new_csrf_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Añade las cookies de sesión (tokens + CSRF) a la respuesta
+pub fn set_session_cookies(jar: CookieJar, tokens: &LoginResponse, csrf_token: &str) -> CookieJar {
+    let refresh_lifetime = auth::REFRESH_TOKEN_TTL_SECONDS as i64;
+
+    jar.add(build_cookie(ACCESS_COOKIE, tokens.access_token.clone(), "/", true, auth::access_token_lifetime_seconds()))
+        .add(build_cookie(REFRESH_COOKIE, tokens.refresh_token.clone(), "/refresh", true, refresh_lifetime))
+        .add(build_cookie(CSRF_COOKIE, csrf_token.to_string(), "/", false, refresh_lifetime))
+}
+
+/// Sustituye solo el access token (la sesión y su refresh token no cambian)
+pub fn set_access_cookie(jar: CookieJar, access_token: &str) -> CookieJar {
+    jar.add(build_cookie(ACCESS_COOKIE, access_token.to_string(), "/", true, auth::access_token_lifetime_seconds()))
+}
+
+/// Cookie que ata el login OIDC al navegador que lo empezó. Lax y no Strict:
+/// la vuelta del IdP es una navegación desde otro sitio y Strict no la enviaría.
+pub fn set_oidc_state_cookie(jar: CookieJar, binding: String, max_age: i64) -> CookieJar {
+    let mut cookie = build_cookie(OIDC_STATE_COOKIE, binding, "/auth/oidc", true, max_age);
+    cookie.set_same_site(SameSite::Lax);
+    jar.add(cookie)
+}
+
+pub fn clear_oidc_state_cookie(jar: CookieJar) -> CookieJar {
+    jar.add(build_cookie(OIDC_STATE_COOKIE, String::new(), "/auth/oidc", true, 0))
+}
+
+/// Borra las cookies de sesión (logout)
+pub fn clear_session_cookies(jar: CookieJar) -> CookieJar {
+    jar.add(build_cookie(ACCESS_COOKIE, String::new(), "/", true, 0))
+        .add(build_cookie(REFRESH_COOKIE, String::new(), "/refresh", true, 0))
+        .add(build_cookie(CSRF_COOKIE, String::new(), "/", false, 0))
+}
+
+/// GET, HEAD y OPTIONS no cambian estado: no necesitan token CSRF
+pub fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Double-submit: la cabecera X-CSRF-Token debe coincidir con la cookie csrf_token
+pub fn csrf_token_matches(jar: &CookieJar, headers: &HeaderMap) -> bool {
+    let cookie = jar.get(CSRF_COOKIE).map(|c| c.value().as_bytes());
+    let header = headers.get(CSRF_HEADER).map(|h| h.as_bytes());
+
+    match (cookie, header) {
+        (Some(cookie), Some(header)) if !cookie.is_empty() && cookie.len() == header.len() => {
+            // Comparación en tiempo constante
+            cookie.iter().zip(header).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+        }
+        _ => false,
+    }
+}
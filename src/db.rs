@@ -1,25 +1,765 @@
 use sqlx::PgPool;
-use crate::{models::{User, DashboardStat, RecentActivity, SystemAlert}, error::AppError};
+use chrono::NaiveDateTime;
+use crate::{audit::AuditEvent, builders::{FieldError, profile::ProfileUpdate}, webauthn::NewCredential, pagination::{self, Page, PageRequest, SortField}, models::{User, ApiKey, OAuthClient, WebAuthnCredential, UserIdentity, AuditEntry, UserTotp, DashboardStat, RecentActivity, SystemAlert}, error::AppError};
 
 
 // --- Users ---
-pub async fn get_all_users(pool: &PgPool) -> Result<Vec<User>, AppError> {
-    let users = sqlx::query_as::<_, User>("SELECT id, username, email, password_hash FROM users")
-        .fetch_all(pool)
-        .await?;
-    Ok(users)
+
+const USER_COLUMNS: &str =
+    "id, username, email, password_hash, email_verified_at, display_name, bio, timezone, locale, avatar_url";
+
+/// Campos por los que se puede ordenar GET /users (el primero es el default)
+pub const USER_SORT_FIELDS: &[SortField] = &[
+    SortField { name: "id", column: "id", sql_type: "INTEGER" },
+    SortField { name: "username", column: "username", sql_type: "TEXT" },
+    SortField { name: "email", column: "email", sql_type: "TEXT" },
+];
+
+pub async fn list_users(
+    pool: &PgPool,
+    page: &PageRequest,
+    username_prefix: Option<&str>,
+    email_domain: Option<&str>,
+) -> Result<Page<User>, AppError> {
+    let users = sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users
+         WHERE deleted_at IS NULL
+           AND ($1::TEXT IS NULL OR username LIKE $1)
+           AND ($2::TEXT IS NULL OR LOWER(SPLIT_PART(email, '@', 2)) = LOWER($2))
+           AND {}
+         ORDER BY {} LIMIT {}",
+        USER_COLUMNS,
+        page.keyset_condition(3),
+        page.order_by(),
+        page.fetch_limit()
+    ))
+    .bind(username_prefix.map(pagination::like_prefix))
+    .bind(email_domain)
+    .bind(page.cursor_value())
+    .bind(page.cursor_id())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(page.build_page(users, |user| match page.sort_name() {
+        "username" => (user.username.clone(), user.id),
+        "email" => (user.email.clone(), user.id),
+        _ => (user.id.to_string(), user.id),
+    }))
 }
 
 pub async fn get_user_by_username(pool: &PgPool, username: &str) -> Result<Option<User>, AppError> {
-    let user = sqlx::query_as::<_, User>("SELECT id, username, email, password_hash FROM users WHERE username = $1")
+    let user = sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE username = $1 AND deleted_at IS NULL", USER_COLUMNS))
         .bind(username)
         .fetch_optional(pool)
         .await?;
     Ok(user)
 }
 
+pub async fn get_user_by_id(pool: &PgPool, id: i32) -> Result<Option<User>, AppError> {
+    let user = sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE id = $1 AND deleted_at IS NULL", USER_COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(user)
+}
+
+/// ¿Hay otra cuenta activa con este email? (sin distinguir mayúsculas)
+pub async fn email_in_use(pool: &PgPool, email: &str, except_user_id: Option<i32>) -> Result<bool, AppError> {
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM users WHERE LOWER(email) = LOWER($1) AND deleted_at IS NULL AND id IS DISTINCT FROM $2)",
+    )
+    .bind(email)
+    .bind(except_user_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(exists)
+}
+
+/// El índice único de emails convierte una carrera entre dos peticiones en un
+/// error de validación del campo, no en un 500
+pub fn email_conflict(e: sqlx::Error) -> AppError {
+    match e {
+        sqlx::Error::Database(db) if db.constraint() == Some("idx_users_email_lower") => {
+            AppError::Validation(vec![email_taken()])
+        }
+        e => e.into(),
+    }
+}
+
+pub fn email_taken() -> FieldError {
+    FieldError::new("email", "taken", "This email is already used by another account")
+}
+
+pub async fn get_user_by_email(pool: &PgPool, email: &str) -> Result<Option<User>, AppError> {
+    let user = sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users WHERE LOWER(email) = LOWER($1) AND deleted_at IS NULL",
+        USER_COLUMNS
+    ))
+    .bind(email)
+    .fetch_optional(pool)
+    .await?;
+    Ok(user)
+}
+
+pub async fn update_password_hash(pool: &PgPool, user_id: i32, password_hash: &str) -> Result<(), AppError> {
+    sqlx::query("UPDATE users SET password_hash = $2 WHERE id = $1")
+        .bind(user_id)
+        .bind(password_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Marca el email como verificado si sigue siendo el mismo que se firmó en el enlace
+pub async fn mark_email_verified(pool: &PgPool, user_id: i32, email: &str) -> Result<bool, AppError> {
+    let result = sqlx::query(
+        "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1 AND email = $2",
+    )
+    .bind(user_id)
+    .bind(email)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Aplica los cambios de perfil validados. Un email distinto al actual vuelve a quedar
+/// sin verificar. None si el usuario no existe.
+pub async fn update_user_profile(pool: &PgPool, id: i32, changes: &ProfileUpdate) -> Result<Option<User>, AppError> {
+    let user = sqlx::query_as::<_, User>(&format!(
+        "UPDATE users SET
+            email_verified_at = CASE WHEN $2::TEXT IS NOT NULL AND $2 <> email THEN NULL ELSE email_verified_at END,
+            email = COALESCE($2, email),
+            display_name = CASE WHEN $3 THEN $4 ELSE display_name END,
+            bio = CASE WHEN $5 THEN $6 ELSE bio END,
+            timezone = CASE WHEN $7 THEN $8 ELSE timezone END,
+            locale = CASE WHEN $9 THEN $10 ELSE locale END
+         WHERE id = $1 AND deleted_at IS NULL
+         RETURNING {}",
+        USER_COLUMNS
+    ))
+    .bind(id)
+    .bind(&changes.email)
+    .bind(changes.display_name.is_some())
+    .bind(changes.display_name.clone().flatten())
+    .bind(changes.bio.is_some())
+    .bind(changes.bio.clone().flatten())
+    .bind(changes.timezone.is_some())
+    .bind(changes.timezone.clone().flatten())
+    .bind(changes.locale.is_some())
+    .bind(changes.locale.clone().flatten())
+    .fetch_optional(pool)
+    .await
+    .map_err(email_conflict)?;
+    Ok(user)
+}
+
+/// Guarda el avatar nuevo (o lo quita con None). Devuelve el usuario y el prefijo
+/// del avatar anterior, para borrar sus ficheros.
+pub async fn set_user_avatar(
+    pool: &PgPool,
+    id: i32,
+    avatar: Option<(&str, &str)>,
+) -> Result<Option<(User, Option<String>)>, AppError> {
+    let mut tx = pool.begin().await?;
+
+    let previous = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT avatar_key FROM users WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(previous) = previous else {
+        return Ok(None);
+    };
+
+    let user = sqlx::query_as::<_, User>(&format!(
+        "UPDATE users SET avatar_key = $2, avatar_url = $3 WHERE id = $1 RETURNING {}",
+        USER_COLUMNS
+    ))
+    .bind(id)
+    .bind(avatar.map(|(key, _)| key))
+    .bind(avatar.map(|(_, url)| url))
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Some((user, previous)))
+}
+
+// --- Borrado de cuentas (RGPD) ---
+
+/// Marca la cuenta como borrada: deja de existir para login y lookups.
+/// Las identidades externas se desvinculan ya (el IdP puede volver a registrarse)
+/// y las API keys se revocan. False si no existía o ya estaba borrada.
+pub async fn soft_delete_user(pool: &PgPool, id: i32) -> Result<bool, AppError> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query("UPDATE users SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query("DELETE FROM user_identities WHERE user_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Borra definitivamente las cuentas marcadas hace más de `grace_days`.
+/// Roles, claves, MFA y passkeys van en cascada; auditoría y actividad quedan anónimas
+/// (la IP de sus entradas de auditoría se borra antes de que el id pase a NULL).
+/// Devuelve (id, avatar_key) de cada cuenta purgada.
+pub async fn purge_deleted_users(pool: &PgPool, grace_days: i32) -> Result<Vec<(i32, Option<String>)>, AppError> {
+    let mut tx = pool.begin().await?;
+
+    let ids: Vec<i32> = sqlx::query_scalar(
+        "SELECT id FROM users WHERE deleted_at < NOW() - make_interval(days => $1) FOR UPDATE",
+    )
+    .bind(grace_days)
+    .fetch_all(&mut *tx)
+    .await?;
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    sqlx::query("UPDATE audit_log SET ip = NULL WHERE actor_id = ANY($1) OR subject_id = ANY($1)")
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+    let purged = sqlx::query_as::<_, (i32, Option<String>)>(
+        "DELETE FROM users WHERE id = ANY($1) RETURNING id, avatar_key",
+    )
+    .bind(&ids)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(purged)
+}
+
+pub async fn get_user_identities(pool: &PgPool, user_id: i32) -> Result<Vec<UserIdentity>, AppError> {
+    let identities = sqlx::query_as::<_, UserIdentity>(
+        "SELECT issuer, subject, created_at FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(identities)
+}
+
+pub async fn get_user_activities(pool: &PgPool, user_id: i32) -> Result<Vec<RecentActivity>, AppError> {
+    let activities = sqlx::query_as::<_, RecentActivity>(&format!(
+        "SELECT {} FROM recent_activities WHERE user_id = $1 ORDER BY id",
+        ACTIVITY_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(activities)
+}
+
+/// Entradas de auditoría en las que el usuario actúa o es el sujeto
+pub async fn get_audit_entries_for_user(pool: &PgPool, user_id: i32) -> Result<Vec<AuditEntry>, AppError> {
+    let entries = sqlx::query_as::<_, AuditEntry>(
+        "SELECT id, actor_id, subject_id, action, method, path, status, token_id, ip, created_at \
+         FROM audit_log WHERE actor_id = $1 OR subject_id = $1 ORDER BY id",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(entries)
+}
+
+// --- Identidades externas (OIDC) ---
+
+/// Usuario vinculado a la identidad (issuer, subject) del IdP
+pub async fn get_user_by_identity(pool: &PgPool, issuer: &str, subject: &str) -> Result<Option<User>, AppError> {
+    let user = sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users WHERE id = (SELECT user_id FROM user_identities WHERE issuer = $1 AND subject = $2) AND deleted_at IS NULL",
+        USER_COLUMNS
+    ))
+    .bind(issuer)
+    .bind(subject)
+    .fetch_optional(pool)
+    .await?;
+    Ok(user)
+}
+
+pub async fn username_exists(pool: &PgPool, username: &str) -> Result<bool, AppError> {
+    let exists = sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM users WHERE username = $1)")
+        .bind(username)
+        .fetch_one(pool)
+        .await?;
+    Ok(exists)
+}
+
+/// Crea el usuario y su identidad externa en una transacción (provisioning JIT).
+/// El password_hash no corresponde a ningún algoritmo, así que el login con
+/// contraseña falla hasta que el usuario defina una con el reset.
+pub async fn create_user_with_identity(
+    pool: &PgPool,
+    username: &str,
+    email: &str,
+    email_verified: bool,
+    issuer: &str,
+    subject: &str,
+) -> Result<User, AppError> {
+    let mut tx = pool.begin().await?;
+
+    let user = sqlx::query_as::<_, User>(&format!(
+        "INSERT INTO users (username, email, password_hash, email_verified_at)
+         VALUES ($1, $2, '!external', CASE WHEN $3 THEN NOW() END)
+         RETURNING {}",
+        USER_COLUMNS
+    ))
+    .bind(username)
+    .bind(email)
+    .bind(email_verified)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(email_conflict)?;
+
+    sqlx::query("INSERT INTO user_identities (user_id, issuer, subject) VALUES ($1, $2, $3)")
+        .bind(user.id)
+        .bind(issuer)
+        .bind(subject)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(user)
+}
+
+// --- Reset de contraseña ---
+
+/// Crea un token de reset e invalida los anteriores que siguieran pendientes
+pub async fn create_password_reset_token(
+    pool: &PgPool,
+    user_id: i32,
+    token_hash: &str,
+    expires_at: NaiveDateTime,
+) -> Result<(), AppError> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("UPDATE password_reset_tokens SET used_at = NOW() WHERE user_id = $1 AND used_at IS NULL")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)")
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Usuario de un token de reset vigente, sin consumirlo
+pub async fn find_password_reset_user(pool: &PgPool, token_hash: &str) -> Result<Option<i32>, AppError> {
+    let user_id = sqlx::query_scalar::<_, i32>(
+        "SELECT user_id FROM password_reset_tokens WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()",
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(user_id)
+}
+
+/// Marca el token como usado y devuelve su usuario (None si no existe, expiró o ya se usó)
+pub async fn consume_password_reset_token(pool: &PgPool, token_hash: &str) -> Result<Option<i32>, AppError> {
+    let user_id = sqlx::query_scalar::<_, i32>(
+        "UPDATE password_reset_tokens SET used_at = NOW() \
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW() RETURNING user_id",
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(user_id)
+}
+
+// --- Roles y permisos ---
+
+pub async fn get_user_roles(pool: &PgPool, user_id: i32) -> Result<Vec<String>, AppError> {
+    let roles = sqlx::query_scalar::<_, String>(
+        "SELECT r.name FROM roles r JOIN user_roles ur ON ur.role_id = r.id WHERE ur.user_id = $1 ORDER BY r.name",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(roles)
+}
+
+pub async fn get_permissions_for_roles(pool: &PgPool, roles: &[String]) -> Result<Vec<String>, AppError> {
+    let permissions = sqlx::query_scalar::<_, String>(
+        "SELECT DISTINCT p.name FROM permissions p \
+         JOIN role_permissions rp ON rp.permission_id = p.id \
+         JOIN roles r ON r.id = rp.role_id \
+         WHERE r.name = ANY($1)",
+    )
+    .bind(roles)
+    .fetch_all(pool)
+    .await?;
+    Ok(permissions)
+}
+
+/// Asigna un rol. Devuelve NotFound si el rol no existe.
+pub async fn grant_role(pool: &PgPool, user_id: i32, role: &str) -> Result<(), AppError> {
+    let role_id = sqlx::query_scalar::<_, i32>("SELECT id FROM roles WHERE name = $1")
+        .bind(role)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Role '{}' not found", role)))?;
+
+    sqlx::query("INSERT INTO user_roles (user_id, role_id) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+        .bind(user_id)
+        .bind(role_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Retira un rol. Devuelve false si el usuario no lo tenía.
+pub async fn revoke_role(pool: &PgPool, user_id: i32, role: &str) -> Result<bool, AppError> {
+    let result = sqlx::query(
+        "DELETE FROM user_roles WHERE user_id = $1 AND role_id = (SELECT id FROM roles WHERE name = $2)",
+    )
+    .bind(user_id)
+    .bind(role)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// --- API Keys ---
+
+const API_KEY_COLUMNS: &str =
+    "id, user_id, name, prefix, scopes, expires_at, last_used_at, revoked_at, created_at";
+
+pub async fn create_api_key(
+    pool: &PgPool,
+    user_id: i32,
+    name: &str,
+    prefix: &str,
+    key_hash: &str,
+    scopes: &[String],
+    expires_at: Option<NaiveDateTime>,
+) -> Result<ApiKey, AppError> {
+    let api_key = sqlx::query_as::<_, ApiKey>(&format!(
+        "INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, expires_at) \
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING {}",
+        API_KEY_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(name)
+    .bind(prefix)
+    .bind(key_hash)
+    .bind(scopes)
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await?;
+    Ok(api_key)
+}
+
+pub async fn get_api_keys_for_user(pool: &PgPool, user_id: i32) -> Result<Vec<ApiKey>, AppError> {
+    let keys = sqlx::query_as::<_, ApiKey>(&format!(
+        "SELECT {} FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC",
+        API_KEY_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(keys)
+}
+
+/// Busca una clave activa (no revocada ni expirada) por su hash
+pub async fn get_active_api_key_by_hash(pool: &PgPool, key_hash: &str) -> Result<Option<ApiKey>, AppError> {
+    let key = sqlx::query_as::<_, ApiKey>(&format!(
+        "SELECT {} FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL \
+         AND (expires_at IS NULL OR expires_at > NOW())",
+        API_KEY_COLUMNS
+    ))
+    .bind(key_hash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(key)
+}
+
+pub async fn touch_api_key(pool: &PgPool, id: i32) -> Result<(), AppError> {
+    sqlx::query("UPDATE api_keys SET last_used_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Revoca una clave del usuario. Devuelve false si no existe o ya estaba revocada.
+pub async fn revoke_api_key(pool: &PgPool, user_id: i32, id: i32) -> Result<bool, AppError> {
+    let result = sqlx::query(
+        "UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// --- Clientes OAuth2 (service principals) ---
+
+const CLIENT_COLUMNS: &str =
+    "id, client_id, name, secret_hash, scopes, created_by, last_used_at, revoked_at, created_at";
+
+pub async fn create_client(
+    pool: &PgPool,
+    client_id: &str,
+    name: &str,
+    secret_hash: &str,
+    scopes: &[String],
+    created_by: i32,
+) -> Result<OAuthClient, AppError> {
+    let client = sqlx::query_as::<_, OAuthClient>(&format!(
+        "INSERT INTO clients (client_id, name, secret_hash, scopes, created_by) \
+         VALUES ($1, $2, $3, $4, $5) RETURNING {}",
+        CLIENT_COLUMNS
+    ))
+    .bind(client_id)
+    .bind(name)
+    .bind(secret_hash)
+    .bind(scopes)
+    .bind(created_by)
+    .fetch_one(pool)
+    .await?;
+    Ok(client)
+}
+
+pub async fn get_clients(pool: &PgPool) -> Result<Vec<OAuthClient>, AppError> {
+    let clients = sqlx::query_as::<_, OAuthClient>(&format!(
+        "SELECT {} FROM clients ORDER BY created_at DESC",
+        CLIENT_COLUMNS
+    ))
+    .fetch_all(pool)
+    .await?;
+    Ok(clients)
+}
+
+/// Busca un cliente no revocado por su client_id
+pub async fn get_active_client(pool: &PgPool, client_id: &str) -> Result<Option<OAuthClient>, AppError> {
+    let client = sqlx::query_as::<_, OAuthClient>(&format!(
+        "SELECT {} FROM clients WHERE client_id = $1 AND revoked_at IS NULL",
+        CLIENT_COLUMNS
+    ))
+    .bind(client_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(client)
+}
+
+pub async fn touch_client(pool: &PgPool, id: i32) -> Result<(), AppError> {
+    sqlx::query("UPDATE clients SET last_used_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Revoca un cliente. Devuelve false si no existe o ya estaba revocado.
+pub async fn revoke_client(pool: &PgPool, id: i32) -> Result<bool, AppError> {
+    let result = sqlx::query("UPDATE clients SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// --- Passkeys (WebAuthn) ---
+
+const WEBAUTHN_COLUMNS: &str =
+    "id, user_id, credential_id, public_key, algorithm, sign_count, transports, name, last_used_at, created_at";
+
+pub async fn create_webauthn_credential(
+    pool: &PgPool,
+    user_id: i32,
+    credential: &NewCredential,
+    name: &str,
+) -> Result<WebAuthnCredential, AppError> {
+    let result = sqlx::query_as::<_, WebAuthnCredential>(&format!(
+        "INSERT INTO webauthn_credentials (user_id, credential_id, public_key, algorithm, sign_count, transports, name) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {}",
+        WEBAUTHN_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(&credential.credential_id)
+    .bind(&credential.public_key)
+    .bind(credential.algorithm)
+    .bind(credential.sign_count)
+    .bind(&credential.transports)
+    .bind(name)
+    .fetch_one(pool)
+    .await;
+
+    match result {
+        Ok(credential) => Ok(credential),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            Err(AppError::BadRequest("Credential already registered".to_string()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn get_webauthn_credentials_for_user(pool: &PgPool, user_id: i32) -> Result<Vec<WebAuthnCredential>, AppError> {
+    let credentials = sqlx::query_as::<_, WebAuthnCredential>(&format!(
+        "SELECT {} FROM webauthn_credentials WHERE user_id = $1 ORDER BY created_at",
+        WEBAUTHN_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(credentials)
+}
+
+pub async fn get_webauthn_credential(pool: &PgPool, credential_id: &str) -> Result<Option<WebAuthnCredential>, AppError> {
+    let credential = sqlx::query_as::<_, WebAuthnCredential>(&format!(
+        "SELECT {} FROM webauthn_credentials WHERE credential_id = $1",
+        WEBAUTHN_COLUMNS
+    ))
+    .bind(credential_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(credential)
+}
+
+/// Guarda el nuevo contador solo si avanza (dos logins concurrentes no lo hacen retroceder)
+pub async fn update_webauthn_sign_count(pool: &PgPool, id: i32, sign_count: i64) -> Result<(), AppError> {
+    sqlx::query(
+        "UPDATE webauthn_credentials SET sign_count = GREATEST(sign_count, $2), last_used_at = NOW() WHERE id = $1",
+    )
+    .bind(id)
+    .bind(sign_count)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// --- Auditoría ---
+
+pub async fn insert_audit_event(pool: &PgPool, event: &AuditEvent<'_>) -> Result<(), AppError> {
+    sqlx::query(
+        "INSERT INTO audit_log (actor_id, subject_id, action, method, path, status, token_id, ip) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+    )
+    .bind(event.actor_id)
+    .bind(event.subject_id)
+    .bind(event.action)
+    .bind(event.method)
+    .bind(event.path)
+    .bind(event.status.map(|s| s as i16))
+    .bind(event.token_id)
+    .bind(event.ip)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// --- MFA (TOTP + códigos de recuperación) ---
+
+pub async fn get_user_totp(pool: &PgPool, user_id: i32) -> Result<Option<UserTotp>, AppError> {
+    let totp = sqlx::query_as::<_, UserTotp>(
+        "SELECT secret, confirmed_at, last_used_step FROM user_totp WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(totp)
+}
+
+/// Guarda un secreto pendiente de confirmar. No toca un TOTP ya confirmado.
+pub async fn upsert_pending_totp(pool: &PgPool, user_id: i32, secret: &str) -> Result<bool, AppError> {
+    let result = sqlx::query(
+        "INSERT INTO user_totp (user_id, secret) VALUES ($1, $2) \
+         ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, last_used_step = NULL, created_at = NOW() \
+         WHERE user_totp.confirmed_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(secret)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Registra el intervalo usado (y confirma el enrolamiento si estaba pendiente).
+/// Devuelve false si otro request ya usó ese intervalo o uno posterior.
+pub async fn mark_totp_step_used(pool: &PgPool, user_id: i32, step: i64) -> Result<bool, AppError> {
+    let result = sqlx::query(
+        "UPDATE user_totp SET last_used_step = $2, confirmed_at = COALESCE(confirmed_at, NOW()) \
+         WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Sustituye todos los códigos de recuperación del usuario
+pub async fn replace_recovery_codes(pool: &PgPool, user_id: i32, code_hashes: &[String]) -> Result<(), AppError> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("INSERT INTO mfa_recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::TEXT[])")
+        .bind(user_id)
+        .bind(code_hashes)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Marca un código de recuperación como usado. Devuelve false si no existe o ya se usó.
+pub async fn use_recovery_code(pool: &PgPool, user_id: i32, code_hash: &str) -> Result<bool, AppError> {
+    let result = sqlx::query(
+        "UPDATE mfa_recovery_codes SET used_at = NOW() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(code_hash)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
 // --- Dashboard (Simuladas como lentas) ---
 
+const ACTIVITY_COLUMNS: &str = "id, description, created_at";
+const ALERT_COLUMNS: &str = "id, message, severity";
+
+/// created_at admite NULL: se compara como epoch para que el keyset no se salte filas
+pub const ACTIVITY_SORT_FIELDS: &[SortField] = &[
+    SortField { name: "created_at", column: "COALESCE(created_at, TIMESTAMP 'epoch')", sql_type: "TIMESTAMP" },
+    SortField { name: "id", column: "id", sql_type: "INTEGER" },
+];
+
+pub const ALERT_SORT_FIELDS: &[SortField] = &[
+    SortField { name: "id", column: "id", sql_type: "INTEGER" },
+    SortField { name: "severity", column: "severity", sql_type: "TEXT" },
+];
+
 pub async fn get_stats(pool: &PgPool) -> Result<Vec<DashboardStat>, AppError> {
     let start = std::time::Instant::now();
     
@@ -32,7 +772,7 @@ pub async fn get_stats(pool: &PgPool) -> Result<Vec<DashboardStat>, AppError> {
 
 pub async fn get_activities(pool: &PgPool) -> Result<Vec<RecentActivity>, AppError> {
     let start = std::time::Instant::now();
-    let activities = sqlx::query_as::<_, RecentActivity>("SELECT description FROM recent_activities")
+    let activities = sqlx::query_as::<_, RecentActivity>(&format!("SELECT {} FROM recent_activities", ACTIVITY_COLUMNS))
         .fetch_all(pool)
         .await?;
     println!("DB: get_activities tardó {:?}", start.elapsed());
@@ -41,9 +781,55 @@ pub async fn get_activities(pool: &PgPool) -> Result<Vec<RecentActivity>, AppErr
 
 pub async fn get_alerts(pool: &PgPool) -> Result<Vec<SystemAlert>, AppError> {
     let start = std::time::Instant::now();
-    let alerts = sqlx::query_as::<_, SystemAlert>("SELECT message, severity FROM system_alerts")
+    let alerts = sqlx::query_as::<_, SystemAlert>(&format!("SELECT {} FROM system_alerts", ALERT_COLUMNS))
         .fetch_all(pool)
         .await?;
     println!("DB: get_alerts tardó {:?}", start.elapsed());
     Ok(alerts)
 }
+
+pub async fn list_activities(pool: &PgPool, page: &PageRequest) -> Result<Page<RecentActivity>, AppError> {
+    let activities = sqlx::query_as::<_, RecentActivity>(&format!(
+        "SELECT {} FROM recent_activities WHERE {} ORDER BY {} LIMIT {}",
+        ACTIVITY_COLUMNS,
+        page.keyset_condition(1),
+        page.order_by(),
+        page.fetch_limit()
+    ))
+    .bind(page.cursor_value())
+    .bind(page.cursor_id())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(page.build_page(activities, |activity| match page.sort_name() {
+        "created_at" => (
+            activity.created_at.unwrap_or(chrono::DateTime::UNIX_EPOCH.naive_utc()).to_string(),
+            activity.id,
+        ),
+        _ => (activity.id.to_string(), activity.id),
+    }))
+}
+
+pub async fn list_alerts(
+    pool: &PgPool,
+    page: &PageRequest,
+    severity: Option<&str>,
+) -> Result<Page<SystemAlert>, AppError> {
+    let alerts = sqlx::query_as::<_, SystemAlert>(&format!(
+        "SELECT {} FROM system_alerts WHERE ($1::TEXT IS NULL OR severity = UPPER($1)) AND {} ORDER BY {} LIMIT {}",
+        ALERT_COLUMNS,
+        page.keyset_condition(2),
+        page.order_by(),
+        page.fetch_limit()
+    ))
+    .bind(severity)
+    .bind(page.cursor_value())
+    .bind(page.cursor_id())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(page.build_page(alerts, |alert| match page.sort_name() {
+        "severity" => (alert.severity.clone(), alert.id),
+        _ => (alert.id.to_string(), alert.id),
+    }))
+}
@@ -0,0 +1,67 @@
+// ============================================================================
+// BORRADO DE CUENTAS (RGPD)
+// ============================================================================
+//
+// DELETE /me no borra la fila al momento:
+//   1. Marca users.deleted_at: la cuenta deja de existir para login, lookups
+//      y tokens (todas las sesiones y access tokens se revocan en el acto).
+//   2. Pasado el periodo de gracia, este job borra la fila. Lo que cuelga del
+//      usuario se borra en cascada; la auditoría y la actividad se conservan
+//      anónimas (user_id a NULL y la IP de la auditoría borrada). Los ficheros
+//      del avatar se borran del storage.
+//
+// El job corre en cada réplica; el DELETE es idempotente, así que no importa
+// que dos lo ejecuten a la vez.
+//
+// Configuración:
+//   ACCOUNT_DELETION_GRACE_DAYS     Días hasta la purga (default: 30)
+//   ACCOUNT_PURGE_INTERVAL_MINUTES  Cada cuánto se buscan cuentas a purgar (default: 60)
+//
+// ============================================================================
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use std::{env, sync::Arc};
+use crate::{avatars, db, storage::Storage};
+
+pub fn grace_days() -> i32 {
+    env::var("ACCOUNT_DELETION_GRACE_DAYS")
+        .unwrap_or_else(|_| "30".to_string())
+        .parse()
+        .unwrap_or(30)
+}
+
+/// Momento a partir del cual se purgará una cuenta borrada ahora
+pub fn purge_after() -> DateTime<Utc> {
+    Utc::now() + Duration::days(grace_days() as i64)
+}
+
+fn purge_interval() -> std::time::Duration {
+    let minutes: u64 = env::var("ACCOUNT_PURGE_INTERVAL_MINUTES")
+        .unwrap_or_else(|_| "60".to_string())
+        .parse()
+        .unwrap_or(60);
+    std::time::Duration::from_secs(minutes.max(1) * 60)
+}
+
+/// Lanza el job en segundo plano (una pasada al arrancar y luego periódicamente)
+pub fn spawn_purge_job(pool: PgPool, storage: Arc<dyn Storage>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(purge_interval());
+        loop {
+            interval.tick().await;
+            match db::purge_deleted_users(&pool, grace_days()).await {
+                Ok(purged) if !purged.is_empty() => {
+                    for avatar_key in purged.iter().filter_map(|(_, key)| key.as_deref()) {
+                        avatars::delete_quietly(storage.as_ref(), avatar_key).await;
+                    }
+                    // Solo ids: el nombre de usuario es justo lo que se está borrando
+                    let ids: Vec<String> = purged.iter().map(|(id, _)| id.to_string()).collect();
+                    tracing::info!("Purgadas {} cuentas borradas (ids: {})", ids.len(), ids.join(", "));
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Fallo al purgar cuentas borradas: {:?}", e),
+            }
+        }
+    });
+}
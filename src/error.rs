@@ -4,24 +4,49 @@ use axum::{
     Json,
 };
 use serde_json::json;
+use crate::builders::FieldError;
 
 // Nuestro tipo de error personalizado
 #[derive(Debug)]
 pub enum AppError {
     DatabaseError(sqlx::Error),
     AuthError(String),
+    BadRequest(String),
+    Forbidden(String),
+    NotFound(String),
+    TooManyRequests(String),
+    Validation(Vec<FieldError>),
 }
 
-// Permitimos usar `?` para convertir automáticamente sqlx::Error en AppError
+impl From<Vec<FieldError>> for AppError {
+    fn from(errors: Vec<FieldError>) -> Self {
+        AppError::Validation(errors)
+    }
+}
+
+// Permitimos usar `?` para convertir automáticamente sqlx::Error en AppError.
+// Un fetch_one sin filas es un 404, no un error interno.
 impl From<sqlx::Error> for AppError {
     fn from(inner: sqlx::Error) -> Self {
-        AppError::DatabaseError(inner)
+        match inner {
+            sqlx::Error::RowNotFound => AppError::NotFound("Resource not found".to_string()),
+            inner => AppError::DatabaseError(inner),
+        }
     }
 }
 
 // Le enseñamos a Axum cómo convertir nuestro error en una respuesta HTTP
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        // Los errores de validación llevan el detalle por campo
+        if let AppError::Validation(fields) = self {
+            let body = Json(json!({
+                "error": "Validation failed",
+                "fields": fields,
+            }));
+            return (StatusCode::UNPROCESSABLE_ENTITY, body).into_response();
+        }
+
         let (status, error_message) = match self {
             AppError::DatabaseError(err) => {
                 // En un entorno real, loguearíamos el error detallado internamente
@@ -32,6 +57,19 @@ impl IntoResponse for AppError {
             AppError::AuthError(msg) => {
                 (StatusCode::UNAUTHORIZED, msg)
             }
+            AppError::BadRequest(msg) => {
+                (StatusCode::BAD_REQUEST, msg)
+            }
+            AppError::Forbidden(msg) => {
+                (StatusCode::FORBIDDEN, msg)
+            }
+            AppError::NotFound(msg) => {
+                (StatusCode::NOT_FOUND, msg)
+            }
+            AppError::TooManyRequests(msg) => {
+                (StatusCode::TOO_MANY_REQUESTS, msg)
+            }
+            AppError::Validation(_) => unreachable!("handled above"),
         };
 
         let body = Json(json!({
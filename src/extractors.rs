@@ -0,0 +1,268 @@
+// ============================================================================
+// EXTRACTORES: Usuario autenticado
+// ============================================================================
+//
+// `auth_middleware` valida el token y deja un `AuthUser` en las extensions de
+// la request. Los handlers solo tienen que pedirlo como argumento:
+//
+//   pub async fn get_dashboard(user: AuthUser, ...) -> ...
+//
+// Si la ruta no pasó por el middleware, el extractor responde 401 en JSON.
+//
+// Los tokens de servicio (client_credentials) no tienen usuario detrás: el
+// middleware deja un `Principal::Service` y `AuthUser` responde 403. Los
+// handlers que aceptan ambos tipos piden `Principal`.
+//
+// ============================================================================
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, Extensions, HeaderMap},
+};
+use lazy_static::lazy_static;
+use std::{
+    convert::Infallible,
+    env,
+    net::{IpAddr, SocketAddr},
+};
+use crate::{cookies, error::AppError, models::Claims};
+
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: i32,
+    pub username: String,
+    /// Claims del JWT (None si la request se autenticó con API key)
+    pub claims: Option<Claims>,
+    /// Permisos efectivos: los de los roles del token o, con API key, sus scopes
+    pub permissions: Vec<String>,
+    /// API key usada para autenticar la request, si la hay
+    pub api_key_id: Option<i32>,
+    /// Admin que está suplantando a este usuario (claim `act`), si lo hay
+    pub impersonator: Option<Impersonator>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Impersonator {
+    pub id: i32,
+    pub username: String,
+}
+
+impl AuthUser {
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+}
+
+/// Cliente OAuth2 autenticado con un token client_credentials
+#[derive(Debug, Clone)]
+pub struct ServicePrincipal {
+    pub id: i32,
+    pub client_id: String,
+    pub name: String,
+    pub claims: Claims,
+    /// Scopes del token que el cliente sigue teniendo concedidos
+    pub permissions: Vec<String>,
+}
+
+/// Quién hace la request: un usuario (JWT o API key) o un servicio
+#[derive(Debug, Clone)]
+pub enum Principal {
+    User(AuthUser),
+    Service(ServicePrincipal),
+}
+
+impl Principal {
+    pub fn has_permission(&self, permission: &str) -> bool {
+        let permissions = match self {
+            Principal::User(user) => &user.permissions,
+            Principal::Service(service) => &service.permissions,
+        };
+        permissions.iter().any(|p| p == permission)
+    }
+
+    /// Nombre para logs: username o client_id
+    pub fn name(&self) -> &str {
+        match self {
+            Principal::User(user) => &user.username,
+            Principal::Service(service) => &service.client_id,
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Principal
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Principal>()
+            .cloned()
+            .ok_or_else(|| AppError::AuthError("Authentication required".to_string()))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<Principal>() {
+            Some(Principal::User(user)) => Ok(user.clone()),
+            Some(Principal::Service(_)) => {
+                Err(AppError::Forbidden("This endpoint requires a user account".to_string()))
+            }
+            None => Err(AppError::AuthError("Authentication required".to_string())),
+        }
+    }
+}
+
+// ============================================================================
+// EXTRACTOR: Información del cliente (dispositivo e IP de la sesión)
+// ============================================================================
+//
+// La IP sale de la conexión TCP (ConnectInfo). X-Forwarded-For solo se tiene
+// en cuenta si la conexión viene de un proxy de confianza (TRUSTED_PROXIES,
+// lista de IPs o rangos CIDR separados por comas): cualquier cliente puede
+// mandar esa cabecera con la IP que quiera.
+//
+// Con proxies encadenados se recorre X-Forwarded-For de derecha a izquierda
+// saltando los proxies de confianza: la primera IP que no lo es es el cliente.
+
+lazy_static! {
+    static ref TRUSTED_PROXIES: Vec<(IpAddr, u8)> = env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let parsed = parse_cidr(entry);
+            if parsed.is_none() {
+                tracing::warn!("TRUSTED_PROXIES: entrada ignorada '{}'", entry);
+            }
+            parsed
+        })
+        .collect();
+}
+
+/// "10.0.0.0/8", "::1" o "192.168.1.10" -> (red, longitud del prefijo)
+fn parse_cidr(entry: &str) -> Option<(IpAddr, u8)> {
+    let (ip, prefix) = match entry.split_once('/') {
+        Some((ip, prefix)) => (ip.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+        None => (entry.parse::<IpAddr>().ok()?, None),
+    };
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(max);
+    (prefix <= max).then_some((ip, prefix))
+}
+
+fn in_network(ip: IpAddr, (network, prefix): (IpAddr, u8)) -> bool {
+    let (ip, network, bits) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => (u32::from(ip) as u128, u32::from(network) as u128, 32),
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (u128::from(ip), u128::from(network), 128),
+        _ => return false,
+    };
+    let shift = bits - prefix as u32;
+    shift >= bits || (ip >> shift) == (network >> shift)
+}
+
+fn is_trusted_proxy(ip: IpAddr) -> bool {
+    TRUSTED_PROXIES.iter().any(|&network| in_network(ip, network))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientInfo::from_parts(&parts.headers, &parts.extensions))
+    }
+}
+
+impl ClientInfo {
+    /// También lo usan los middlewares, que tienen la request completa
+    pub fn from_parts(headers: &HeaderMap, extensions: &Extensions) -> Self {
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        let peer = extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        let ip = peer.map(|peer| client_ip(peer, headers).to_string());
+
+        ClientInfo { user_agent, ip }
+    }
+}
+
+/// IP del cliente: el peer TCP, salvo que sea un proxy de confianza
+fn client_ip(peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    if !is_trusted_proxy(peer) {
+        return peer;
+    }
+
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+
+    // Todos los saltos son de confianza: el más lejano es el cliente
+    forwarded
+        .iter()
+        .rev()
+        .find(|ip| !is_trusted_proxy(**ip))
+        .or(forwarded.first())
+        .copied()
+        .unwrap_or(peer)
+}
+
+// ============================================================================
+// EXTRACTOR: Modo de entrega de los tokens (JSON o cookies HttpOnly)
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionMode {
+    /// Tokens en el body JSON (default, clientes con Authorization: Bearer)
+    Bearer,
+    /// Tokens en cookies HttpOnly (navegadores), pedido con `X-Session-Mode: cookie`
+    Cookie,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SessionMode
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let cookie_mode = parts
+            .headers
+            .get(cookies::SESSION_MODE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.eq_ignore_ascii_case("cookie"));
+
+        Ok(if cookie_mode { SessionMode::Cookie } else { SessionMode::Bearer })
+    }
+}
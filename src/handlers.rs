@@ -1,21 +1,347 @@
 use axum::{
-    extract::State,
-    Json,
+    extract::{Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, Redirect},
+    Form, Json,
 };
+use axum_extra::extract::cookie::CookieJar;
 use tokio::time::Instant;
 use crate::{
-    models::{User, DashboardData, AppState, LoginRequest, LoginResponse, RefreshRequest},
-    db, error::AppError, cache, auth, rate_limit,
-    builders::UserRegistration,  // TYPE-STATE BUILDER
+    models::{
+        User, DashboardData, AppState, LoginRequest, LoginResponse, RefreshRequest,
+        RoleRequest, UserRolesResponse, ApiKey, CreateApiKeyRequest, CreateApiKeyResponse,
+        LoginOutcome, MfaChallengeResponse, MfaLoginRequest, TotpEnrollResponse, TotpCodeRequest,
+        RecoveryCodesResponse, ForgotPasswordRequest, ResetPasswordRequest, ChangePasswordRequest,
+        PasswordChangedResponse,
+        RegisterRequest, VerifyEmailQuery, EmailVerificationRequiredResponse, SessionResponse,
+        RevokeTokenRequest, OidcLoginQuery, OidcCallbackQuery, OAuthClient, CreateClientRequest,
+        CreateClientResponse, TokenRequest, ClientTokenResponse, CookieSessionResponse,
+        ImpersonationResponse, ImpersonatedUser, WebAuthnCredential, RegistrationCredential,
+        AssertionCredential, PasskeyLoginStartRequest, MagicLinkRequest, MagicLinkVerifyRequest,
+        UpdateUserRequest, ListUsersQuery, DashboardListQuery, RecentActivity, SystemAlert,
+        UserDataExport, AccountDeletionResponse,
+    },
+    pagination::{Page, PageRequest},
+    visibility::{Masked, Viewer},
+    db, error::AppError, cache, auth, rate_limit, jwt_keys, api_keys, mfa, mailer, revocation,
+    lockout, metrics, oidc, oauth_clients, cookies, audit, middleware, webauthn, erasure, avatars,
+    builders::{UserRegistration, FieldError, password_policy, profile},  // TYPE-STATE BUILDER
+    extractors::{AuthUser, ClientInfo, Principal, SessionMode},
 };
 
-pub async fn list_users(State(state): State<AppState>) -> Result<Json<Vec<User>>, AppError> {
+pub async fn list_users(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Query(query): Query<ListUsersQuery>,
+) -> Result<Json<Page<Masked<User>>>, AppError> {
+    let viewer = Viewer::from(&user);
+    let page = PageRequest::parse(query.limit, query.after.as_deref(), query.sort.as_deref(), db::USER_SORT_FIELDS)?;
+
+    // Filtrar u ordenar por email revelaría los emails que ocultamos en la respuesta
+    if !viewer.is_privileged() && (query.email_domain.is_some() || page.sort_name() == "email") {
+        return Err(AppError::Forbidden("Filtering or sorting by email requires users:read".to_string()));
+    }
+
     // Accedemos al pool a través del state
-    let users = db::get_all_users(&state.pool).await?;
-    Ok(Json(users))
+    let users = db::list_users(
+        &state.pool,
+        &page,
+        query.username_prefix.as_deref().filter(|p| !p.is_empty()),
+        query.email_domain.as_deref().map(|d| d.trim_start_matches('@')).filter(|d| !d.is_empty()),
+    )
+    .await?;
+    Ok(Json(viewer.mask_page(users)))
+}
+
+/// Cada usuario gestiona su propia cuenta; con el permiso (admins), cualquiera
+fn require_self_or_permission(user: &AuthUser, target_id: i32, permission: &str) -> Result<(), AppError> {
+    if user.id == target_id || user.has_permission(permission) {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden("You can only access your own account".to_string()))
+    }
+}
+
+async fn find_user(state: &AppState, id: i32) -> Result<User, AppError> {
+    db::get_user_by_id(&state.pool, id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User {} not found", id)))
+}
+
+pub async fn get_me(user: AuthUser, State(state): State<AppState>) -> Result<Json<User>, AppError> {
+    Ok(Json(find_user(&state, user.id).await?))
+}
+
+/// Exportación RGPD (derecho de acceso): todo lo que guardamos del usuario
+pub async fn export_me(
+    user: AuthUser,
+    client: ClientInfo,
+    State(state): State<AppState>,
+) -> Result<Json<UserDataExport>, AppError> {
+    require_interactive_session(&user)?;
+
+    let account = find_user(&state, user.id).await?;
+    let current = user.claims.as_ref().and_then(|c| c.sid.as_deref());
+    let sessions = auth::list_sessions(&state.redis_client, &user.username)
+        .await?
+        .into_iter()
+        .map(|(id, session)| SessionResponse {
+            current: current == Some(id.as_str()),
+            id,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: session.created_at,
+            last_refresh_at: session.last_refresh_at,
+        })
+        .collect();
+
+    let export = UserDataExport {
+        exported_at: chrono::Utc::now(),
+        roles: db::get_user_roles(&state.pool, user.id).await?,
+        identities: db::get_user_identities(&state.pool, user.id).await?,
+        api_keys: db::get_api_keys_for_user(&state.pool, user.id).await?,
+        passkeys: db::get_webauthn_credentials_for_user(&state.pool, user.id).await?,
+        totp_enabled: db::get_user_totp(&state.pool, user.id).await?.is_some_and(|t| t.confirmed_at.is_some()),
+        sessions,
+        activities: db::get_user_activities(&state.pool, user.id).await?,
+        audit_log: db::get_audit_entries_for_user(&state.pool, user.id).await?,
+        user: account,
+    };
+
+    audit::record(&state.pool, audit::AuditEvent {
+        actor_id: Some(user.id),
+        subject_id: Some(user.id),
+        action: audit::ACCOUNT_EXPORT,
+        ip: client.ip.as_deref(),
+        ..Default::default()
+    })
+    .await?;
+
+    Ok(Json(export))
+}
+
+/// Borrado RGPD: la cuenta deja de funcionar ya y se purga tras el periodo de gracia
+pub async fn delete_me(
+    user: AuthUser,
+    client: ClientInfo,
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> Result<(StatusCode, CookieJar, Json<AccountDeletionResponse>), AppError> {
+    require_interactive_session(&user)?;
+
+    let account = find_user(&state, user.id).await?;
+    close_account(&state, &user, &account, &client).await?;
+
+    let jar = if jar.get(cookies::ACCESS_COOKIE).is_some() {
+        cookies::clear_session_cookies(jar)
+    } else {
+        jar
+    };
+
+    Ok((StatusCode::ACCEPTED, jar, Json(account_deletion_response())))
+}
+
+fn account_deletion_response() -> AccountDeletionResponse {
+    AccountDeletionResponse {
+        message: "Account deleted. Remaining data will be purged after the grace period.".to_string(),
+        purge_after: erasure::purge_after(),
+    }
+}
+
+/// Borrado lógico de una cuenta (por su dueño o por un admin): deja de funcionar
+/// ya, se audita y el job de purga la elimina tras el periodo de gracia
+async fn close_account(state: &AppState, actor: &AuthUser, account: &User, client: &ClientInfo) -> Result<(), AppError> {
+    if !db::soft_delete_user(&state.pool, account.id).await? {
+        return Err(AppError::NotFound(format!("User {} not found", account.id)));
+    }
+
+    // Ninguna sesión ni access token emitido hasta ahora sigue valiendo
+    auth::revoke_all_sessions(&state.redis_client, &account.username).await?;
+    revocation::revoke_tokens_issued_before_now(&state.redis_client, &account.username).await?;
+
+    audit::record(&state.pool, audit::AuditEvent {
+        actor_id: Some(actor.id),
+        subject_id: Some(account.id),
+        action: audit::ACCOUNT_DELETION,
+        ip: client.ip.as_deref(),
+        ..Default::default()
+    })
+    .await?;
+    tracing::info!("{} ha borrado la cuenta {} (id {})", actor.username, account.username, account.id);
+    Ok(())
+}
+
+/// Perfil de cualquier usuario: completo para él mismo y los admins, público para el resto
+pub async fn get_user(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<Masked<User>>, AppError> {
+    Ok(Json(Viewer::from(&user).mask(find_user(&state, id).await?)))
+}
+
+pub async fn update_user(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Json(payload): Json<UpdateUserRequest>,
+) -> Result<Json<User>, AppError> {
+    require_self_or_permission(&user, id, "users:write")?;
+
+    let changes = profile::validate(&payload)?;
+
+    let current = find_user(&state, id).await?;
+    let email_changed = changes.email.as_ref().is_some_and(|e| *e != current.email);
+
+    // Cambiar el email propio abre la puerta al reset de contraseña: solo en sesión interactiva
+    if email_changed && user.id == id {
+        require_interactive_session(&user)?;
+    }
+
+    // Un email, una cuenta: si no, forgot-password o el magic link irían a otra
+    if let Some(email) = changes.email.as_deref().filter(|_| email_changed) {
+        if db::email_in_use(&state.pool, email, Some(id)).await? {
+            return Err(AppError::Validation(vec![db::email_taken()]));
+        }
+    }
+
+    let updated = db::update_user_profile(&state.pool, id, &changes)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User {} not found", id)))?;
+
+    if email_changed {
+        tracing::info!("{} ha cambiado el email de {} (id {})", user.username, updated.username, id);
+        send_verification_email(&state, updated.id, &updated.username, &updated.email).await?;
+    }
+
+    Ok(Json(updated))
+}
+
+/// Mismo borrado que DELETE /me: con periodo de gracia y auditado, también cuando lo hace un admin
+pub async fn delete_user(
+    user: AuthUser,
+    client: ClientInfo,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<(StatusCode, Json<AccountDeletionResponse>), AppError> {
+    require_self_or_permission(&user, id, "users:write")?;
+    if user.id == id {
+        require_interactive_session(&user)?;
+    }
+
+    let target = find_user(&state, id).await?;
+    close_account(&state, &user, &target, &client).await?;
+
+    Ok((StatusCode::ACCEPTED, Json(account_deletion_response())))
+}
+
+/// Sube un avatar (multipart, campo "avatar") y sustituye al anterior
+pub async fn upload_avatar(
+    user: AuthUser,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Json<User>, AppError> {
+    let too_large = || {
+        AppError::Validation(vec![FieldError::new(
+            "avatar",
+            "too_large",
+            format!("Must be at most {} bytes", avatars::max_upload_bytes()),
+        )])
+    };
+    // Si el cuerpo entero pasa del DefaultBodyLimit de la ruta, axum lo reporta como 413
+    let invalid = |e: axum::extract::multipart::MultipartError| match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => too_large(),
+        _ => AppError::BadRequest("Invalid multipart body".to_string()),
+    };
+
+    let mut upload = None;
+    while let Some(mut field) = multipart.next_field().await.map_err(invalid)? {
+        if field.name() != Some("avatar") {
+            continue;
+        }
+
+        // Leer por trozos para cortar en cuanto se pase del límite
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.chunk().await.map_err(invalid)? {
+            if bytes.len() + chunk.len() > avatars::max_upload_bytes() {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        upload = Some(bytes);
+        break;
+    }
+
+    let bytes = upload.ok_or_else(|| {
+        AppError::Validation(vec![FieldError::new("avatar", "required", "An image file is required")])
+    })?;
+
+    let thumbnails = avatars::make_thumbnails(bytes).await?;
+    let (key, url) = avatars::store_thumbnails(state.storage.as_ref(), user.id, thumbnails).await?;
+
+    let (updated, previous) = match db::set_user_avatar(&state.pool, user.id, Some((&key, &url))).await? {
+        Some(result) => result,
+        None => {
+            avatars::delete_quietly(state.storage.as_ref(), &key).await;
+            return Err(AppError::NotFound(format!("User {} not found", user.id)));
+        }
+    };
+    if let Some(previous) = previous {
+        avatars::delete_quietly(state.storage.as_ref(), &previous).await;
+    }
+
+    tracing::info!("{} ha cambiado su avatar", user.username);
+    Ok(Json(updated))
+}
+
+pub async fn delete_avatar(user: AuthUser, State(state): State<AppState>) -> Result<Json<User>, AppError> {
+    let (updated, previous) = db::set_user_avatar(&state.pool, user.id, None)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User {} not found", user.id)))?;
+
+    if let Some(previous) = previous {
+        avatars::delete_quietly(state.storage.as_ref(), &previous).await;
+    }
+    Ok(Json(updated))
+}
+
+/// Sirve los ficheros del storage local (un backend S3 tendría su propia URL pública)
+pub async fn serve_media(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<([(header::HeaderName, &'static str); 3], Vec<u8>), AppError> {
+    let object = state
+        .storage
+        .get(&key)
+        .await?
+        .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, object.content_type),
+            // Cada subida tiene una clave nueva: el contenido de una URL nunca cambia
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        object.bytes,
+    ))
 }
 
-pub async fn get_dashboard(State(state): State<AppState>) -> Result<Json<DashboardData>, AppError> {
+pub async fn get_dashboard(
+    principal: Principal,
+    State(state): State<AppState>,
+) -> Result<Json<DashboardData>, AppError> {
+    match &principal {
+        Principal::User(user) => tracing::debug!(
+            "Dashboard solicitado por {} (id {}, api key {:?}, token exp {:?})",
+            user.username, user.id, user.api_key_id, user.claims.as_ref().map(|c| c.exp)
+        ),
+        Principal::Service(service) => tracing::debug!(
+            "Dashboard solicitado por el servicio {} ({}, id {}, token exp {})",
+            service.client_id, service.name, service.id, service.claims.exp
+        ),
+    }
+
     // 1. INTENTAR LEER DE REDIS (Cache Distribuido)
     if let Some(data) = cache::get_dashboard_data(&state.redis_client).await? {
         println!("REDIS CACHE HIT!");
@@ -48,34 +374,451 @@ pub async fn get_dashboard(State(state): State<AppState>) -> Result<Json<Dashboa
     Ok(Json(data))
 }
 
+/// Actividad reciente paginada (por defecto, la más nueva primero)
+pub async fn list_activities(
+    State(state): State<AppState>,
+    Query(query): Query<DashboardListQuery>,
+) -> Result<Json<Page<RecentActivity>>, AppError> {
+    let sort = query.sort.as_deref().unwrap_or("-created_at");
+    let page = PageRequest::parse(query.limit, query.after.as_deref(), Some(sort), db::ACTIVITY_SORT_FIELDS)?;
+    Ok(Json(db::list_activities(&state.pool, &page).await?))
+}
+
+pub async fn list_alerts(
+    State(state): State<AppState>,
+    Query(query): Query<DashboardListQuery>,
+) -> Result<Json<Page<SystemAlert>>, AppError> {
+    let page = PageRequest::parse(query.limit, query.after.as_deref(), query.sort.as_deref(), db::ALERT_SORT_FIELDS)?;
+    Ok(Json(db::list_alerts(&state.pool, &page, query.severity.as_deref()).await?))
+}
+
+/// Emite el par access + refresh token para un usuario ya autenticado
+async fn issue_tokens(
+    state: &AppState,
+    user_id: i32,
+    username: &str,
+    client: &ClientInfo,
+) -> Result<LoginResponse, AppError> {
+    // Cada login abre una sesión nueva (dispositivo, IP...) ligada al refresh token
+    let refresh_token = auth::create_refresh_token();
+    let session_id = auth::store_refresh_token(&state.redis_client, username, &refresh_token, client).await?;
+
+    // Roles actuales del usuario embebidos en el access token
+    let roles = db::get_user_roles(&state.pool, user_id).await?;
+    let access_token = auth::create_jwt(username, &roles, Some(&session_id))?;
+
+    Ok(LoginResponse { access_token, refresh_token })
+}
+
+/// Con TOTP activo, el primer factor (contraseña, enlace, IdP...) solo da acceso
+/// al segundo paso (/login/mfa). None si el usuario no tiene MFA.
+async fn mfa_challenge(state: &AppState, user: &User) -> Result<Option<LoginOutcome>, AppError> {
+    let totp = db::get_user_totp(&state.pool, user.id).await?;
+    if totp.is_none_or(|t| t.confirmed_at.is_none()) {
+        return Ok(None);
+    }
+
+    let mfa_token = auth::create_mfa_pending_token(&state.redis_client, &user.username).await?;
+    Ok(Some(LoginOutcome::MfaRequired(MfaChallengeResponse { mfa_required: true, mfa_token })))
+}
+
+/// Entrega los tokens según el modo pedido: en el JSON o en cookies HttpOnly
+fn deliver_tokens(mode: SessionMode, jar: CookieJar, tokens: LoginResponse) -> (CookieJar, Json<LoginOutcome>) {
+    match mode {
+        SessionMode::Bearer => (jar, Json(LoginOutcome::Tokens(tokens))),
+        SessionMode::Cookie => {
+            let csrf_token = cookies::new_csrf_token();
+            let jar = cookies::set_session_cookies(jar, &tokens, &csrf_token);
+            (jar, Json(LoginOutcome::CookieSession(CookieSessionResponse {
+                csrf_token,
+                expires_in: auth::access_token_lifetime_seconds(),
+            })))
+        }
+    }
+}
+
 pub async fn login(
     State(state): State<AppState>,
+    client: ClientInfo,
+    mode: SessionMode,
+    jar: CookieJar,
     Json(payload): Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, AppError> {
-    // Rate limiting por username
-    let rate_key = format!("rate_limit:login:{}", payload.username);
-    if !rate_limit::check_rate_limit(&state.redis_client, &rate_key).await? {
-        return Err(AppError::AuthError("Too many requests. Try again later.".to_string()));
+) -> Result<(CookieJar, Json<LoginOutcome>), AppError> {
+    // 0. Rate limit por IP y por username (aunque los intentos acierten o
+    //    vayan cambiando de cuenta) y bloqueo progresivo por fallos
+    let mut rate_keys = vec![format!("rate_limit:login:{}", payload.username)];
+    if let Some(ip) = &client.ip {
+        rate_keys.push(format!("rate_limit:login:ip:{}", ip));
+    }
+    for rate_key in &rate_keys {
+        if !rate_limit::check_rate_limit(&state.redis_client, rate_key).await? {
+            metrics::record_auth_attempt(false);
+            return Err(AppError::TooManyRequests("Too many requests. Try again later.".to_string()));
+        }
+    }
+
+    if let Some(seconds) = lockout::locked_for(&state.redis_client, &payload.username, client.ip.as_deref()).await? {
+        metrics::record_auth_attempt(false);
+        return Err(AppError::TooManyRequests(format!(
+            "Too many failed attempts. Try again in {} seconds.",
+            seconds
+        )));
     }
 
     // 1. Buscar usuario
     let user = db::get_user_by_username(&state.pool, &payload.username).await?;
 
-    if let Some(user) = user {
-        // 2. Verificar password
-        if auth::verify_password(&payload.password, &user.password_hash)? {
-            // 3. Generar tokens
-            let access_token = auth::create_jwt(&user.username)?;
-            let refresh_token = auth::create_refresh_token();
-            
-            // 4. Guardar refresh token en Redis
-            auth::store_refresh_token(&state.redis_client, &user.username, &refresh_token).await?;
-            
-            return Ok(Json(LoginResponse { access_token, refresh_token }));
+    // 2. Verificar password. Si el usuario no existe se verifica contra hashes
+    //    señuelo para que la respuesta tarde lo mismo (no revela qué cuentas existen)
+    let authenticated = auth::verify_login_password(
+        &payload.password,
+        user.as_ref().map(|user| user.password_hash.as_str()),
+    )
+    .await?;
+
+    let user = match user {
+        Some(user) if authenticated => user,
+        _ => {
+            metrics::record_auth_attempt(false);
+            lockout::record_failure(&state.redis_client, &payload.username, client.ip.as_deref()).await?;
+            return Err(AppError::AuthError("Credenciales inválidas".to_string()));
         }
+    };
+
+    metrics::record_auth_attempt(true);
+    lockout::record_success(&state.redis_client, &user.username, client.ip.as_deref()).await?;
+
+    // Migración transparente del hash (bcrypt -> Argon2id, parámetros más fuertes...)
+    if auth::password_needs_rehash(&user.password_hash) {
+        match auth::hash_password(&payload.password).await {
+            Ok(new_hash) => {
+                db::update_password_hash(&state.pool, user.id, &new_hash).await?;
+                tracing::info!("Hash de contraseña de {} actualizado", user.username);
+            }
+            Err(_) => tracing::warn!("No se pudo rehashear la contraseña de {}", user.username),
+        }
+    }
+
+    // 3. Opcionalmente, no se entra hasta verificar el email
+    if auth::email_verification_required() && user.email_verified_at.is_none() {
+        return Err(AppError::Forbidden("Email not verified".to_string()));
     }
 
-    Err(AppError::AuthError("Credenciales inválidas".to_string()))
+    // 4. Con TOTP activo, la contraseña solo da acceso al segundo paso
+    if let Some(challenge) = mfa_challenge(&state, &user).await? {
+        return Ok((jar, Json(challenge)));
+    }
+
+    // 5. Generar tokens
+    let tokens = issue_tokens(&state, user.id, &user.username, &client).await?;
+    Ok(deliver_tokens(mode, jar, tokens))
+}
+
+/// Segundo paso del login: mfa_token + código TOTP (o código de recuperación)
+pub async fn login_mfa(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    mode: SessionMode,
+    jar: CookieJar,
+    Json(payload): Json<MfaLoginRequest>,
+) -> Result<(CookieJar, Json<LoginOutcome>), AppError> {
+    let invalid = || AppError::AuthError("Invalid or expired MFA challenge".to_string());
+
+    // Pocos intentos por reto: 6 dígitos no aguantan fuerza bruta
+    let rate_key = format!("rate_limit:mfa:{}", payload.mfa_token);
+    if !rate_limit::check_rate_limit(&state.redis_client, &rate_key).await? {
+        return Err(AppError::TooManyRequests("Too many MFA attempts. Log in again.".to_string()));
+    }
+
+    let username = auth::get_mfa_pending_user(&state.redis_client, &payload.mfa_token)
+        .await?
+        .ok_or_else(invalid)?;
+    let user = db::get_user_by_username(&state.pool, &username).await?.ok_or_else(invalid)?;
+
+    let verified = match (&payload.code, &payload.recovery_code) {
+        (Some(code), _) => {
+            let totp = db::get_user_totp(&state.pool, user.id).await?.ok_or_else(invalid)?;
+            match mfa::verify_totp(&totp.secret, code, totp.last_used_step) {
+                Some(step) => db::mark_totp_step_used(&state.pool, user.id, step).await?,
+                None => false,
+            }
+        }
+        (None, Some(recovery_code)) => {
+            let used = db::use_recovery_code(&state.pool, user.id, &mfa::hash_recovery_code(recovery_code)).await?;
+            if used {
+                tracing::warn!("{} ha usado un código de recuperación MFA", user.username);
+            }
+            used
+        }
+        (None, None) => return Err(AppError::BadRequest("code or recovery_code is required".to_string())),
+    };
+
+    metrics::record_auth_attempt(verified);
+    if !verified {
+        return Err(AppError::AuthError("Invalid MFA code".to_string()));
+    }
+
+    // El reto es de un solo uso
+    if !auth::consume_mfa_pending_token(&state.redis_client, &payload.mfa_token).await? {
+        return Err(invalid());
+    }
+
+    let tokens = issue_tokens(&state, user.id, &user.username, &client).await?;
+    Ok(deliver_tokens(mode, jar, tokens))
+}
+
+// --- Magic links (login sin contraseña) ---
+
+/// Envía un enlace de login de un solo uso. Responde igual exista o no el email.
+pub async fn request_magic_link(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<MagicLinkRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    // Por email (no inundar un buzón) y por IP (no recorrer listas de emails)
+    let mut rate_keys = vec![format!("rate_limit:magic_link:{}", payload.email.to_lowercase())];
+    if let Some(ip) = &client.ip {
+        rate_keys.push(format!("rate_limit:magic_link_ip:{}", ip));
+    }
+    for rate_key in &rate_keys {
+        if !rate_limit::check_rate_limit(&state.redis_client, rate_key).await? {
+            return Err(AppError::TooManyRequests("Too many requests. Try again later.".to_string()));
+        }
+    }
+
+    // En segundo plano: ni el tiempo de respuesta ni un fallo del mailer
+    // revelan si el email tiene cuenta
+    let task_state = state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = send_magic_link(&task_state, &payload.email).await {
+            tracing::error!("No se pudo enviar el enlace de acceso: {:?}", e);
+        }
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "message": "If the email exists, a login link has been sent" })),
+    ))
+}
+
+async fn send_magic_link(state: &AppState, email: &str) -> Result<(), AppError> {
+    let Some(user) = db::get_user_by_email(&state.pool, email).await? else {
+        return Ok(());
+    };
+    let token = auth::create_magic_link_token(&state.redis_client, user.id, &user.email).await?;
+
+    state.mailer.send(mailer::Email {
+        to: user.email,
+        subject: "Tu enlace de acceso".to_string(),
+        body: format!(
+            "Hola {},\n\nPara entrar sin contraseña usa este enlace (caduca en {} minutos y solo funciona una vez):\n\n{}/login/magic/verify?token={}\n\nSi no lo has pedido tú, ignora este email.",
+            user.username, auth::magic_link_ttl_minutes(), mailer::app_base_url(), token
+        ),
+    }).await
+}
+
+/// Página a la que apunta el enlace del email. NO consume el token: los
+/// escáneres de correo y el prefetch de los clientes abren los enlaces con GET
+/// y lo gastarían antes que el usuario. El canje lo hace el POST que lanza el
+/// botón (la página lee el token de su propia URL; no lo interpolamos en el HTML).
+pub async fn magic_link_landing() -> ([(header::HeaderName, &'static str); 2], Html<&'static str>) {
+    (
+        [
+            (header::CACHE_CONTROL, "no-store"),
+            // El token va en la URL: que no se filtre a otros sitios vía Referer
+            (header::REFERRER_POLICY, "no-referrer"),
+        ],
+        Html(MAGIC_LINK_LANDING_PAGE),
+    )
+}
+
+const MAGIC_LINK_LANDING_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="es">
+<head><meta charset="utf-8"><meta name="robots" content="noindex"><title>Iniciar sesión</title></head>
+<body>
+  <p>Pulsa el botón para iniciar sesión con este enlace.</p>
+  <button id="login">Iniciar sesión</button>
+  <p id="result"></p>
+  <script>
+    document.getElementById("login").addEventListener("click", async () => {
+      const token = new URLSearchParams(location.search).get("token") || "";
+      const response = await fetch("/login/magic/verify", {
+        method: "POST",
+        headers: { "Content-Type": "application/json", "X-Session-Mode": "cookie" },
+        body: JSON.stringify({ token }),
+      });
+      const body = await response.json().catch(() => ({}));
+      document.getElementById("result").textContent = !response.ok
+        ? (body.error || "No se pudo iniciar sesión")
+        : body.mfa_required
+          ? "Falta el segundo paso: introduce el código de tu app de autenticación."
+          : "Sesión iniciada. Ya puedes cerrar esta pestaña.";
+    });
+  </script>
+</body>
+</html>
+"#;
+
+/// Canjea el enlace (token en el body) por el mismo resultado que /login
+pub async fn verify_magic_link(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    mode: SessionMode,
+    jar: CookieJar,
+    Json(payload): Json<MagicLinkVerifyRequest>,
+) -> Result<(CookieJar, Json<LoginOutcome>), AppError> {
+    let (user_id, email) = match auth::consume_magic_link_token(&state.redis_client, &payload.token).await {
+        Ok(link) => link,
+        Err(e) => {
+            metrics::record_auth_attempt(false);
+            return Err(e);
+        }
+    };
+
+    let user = db::get_user_by_id(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::AuthError("Invalid login link".to_string()))?;
+
+    // Enlace enviado a un email que ya no es el de la cuenta
+    if !user.email.eq_ignore_ascii_case(&email) {
+        metrics::record_auth_attempt(false);
+        return Err(AppError::AuthError("Invalid login link".to_string()));
+    }
+
+    metrics::record_auth_attempt(true);
+    lockout::record_success(&state.redis_client, &user.username, client.ip.as_deref()).await?;
+
+    // Abrir el enlace demuestra que el buzón es suyo
+    if user.email_verified_at.is_none() {
+        db::mark_email_verified(&state.pool, user.id, &user.email).await?;
+    }
+
+    // El enlace sustituye a la contraseña, no al segundo factor
+    if let Some(challenge) = mfa_challenge(&state, &user).await? {
+        return Ok((jar, Json(challenge)));
+    }
+
+    let tokens = issue_tokens(&state, user.id, &user.username, &client).await?;
+    Ok(deliver_tokens(mode, jar, tokens))
+}
+
+// --- Login con OpenID Connect ---
+
+/// Redirige al IdP con state, nonce y code_challenge (PKCE). Un navegador que
+/// navega aquí no puede mandar cabeceras: el modo cookie se pide con ?session_mode=cookie
+pub async fn oidc_login(
+    State(state): State<AppState>,
+    mode: SessionMode,
+    jar: CookieJar,
+    Query(query): Query<OidcLoginQuery>,
+) -> Result<(CookieJar, Redirect), AppError> {
+    let cookie_session = mode == SessionMode::Cookie
+        || query.session_mode.as_deref().is_some_and(|m| m.eq_ignore_ascii_case("cookie"));
+
+    let (url, oidc_state) =
+        oidc::authorization_url(&state.redis_client, query.login_hint.as_deref(), cookie_session).await?;
+
+    let jar = cookies::set_oidc_state_cookie(jar, oidc::state_binding(&oidc_state), oidc::STATE_TTL_SECONDS as i64);
+    Ok((jar, Redirect::to(&url)))
+}
+
+/// Vuelta del IdP: valida el ID token, provisiona al usuario y emite nuestros tokens
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Result<(CookieJar, Json<LoginOutcome>), AppError> {
+    // La cookie del state solo vale para este intento, salga como salga
+    let binding = jar.get(cookies::OIDC_STATE_COOKIE).map(|cookie| cookie.value().to_owned());
+    let jar = cookies::clear_oidc_state_cookie(jar);
+
+    if let Some(error) = query.error {
+        metrics::record_auth_attempt(false);
+        tracing::warn!("El IdP devolvió un error: {} ({:?})", error, query.error_description);
+        return Err(AppError::AuthError(format!("Identity provider error: {}", error)));
+    }
+
+    let (code, oidc_state) = query
+        .code
+        .zip(query.state)
+        .ok_or_else(|| AppError::BadRequest("code and state are required".to_string()))?;
+
+    let (claims, cookie_session) =
+        match oidc::complete_login(&state.redis_client, &code, &oidc_state, binding.as_deref()).await {
+            Ok(result) => result,
+            Err(e) => {
+                metrics::record_auth_attempt(false);
+                return Err(e);
+            }
+        };
+
+    let user = match db::get_user_by_identity(&state.pool, &claims.iss, &claims.sub).await? {
+        Some(user) => user,
+        None => provision_oidc_user(&state, &claims).await?,
+    };
+
+    metrics::record_auth_attempt(true);
+
+    if auth::email_verification_required() && user.email_verified_at.is_none() {
+        return Err(AppError::Forbidden("Email not verified".to_string()));
+    }
+
+    // El IdP sustituye a la contraseña, no a nuestro segundo factor
+    if let Some(challenge) = mfa_challenge(&state, &user).await? {
+        return Ok((jar, Json(challenge)));
+    }
+
+    let mode = if cookie_session { SessionMode::Cookie } else { SessionMode::Bearer };
+    let tokens = issue_tokens(&state, user.id, &user.username, &client).await?;
+    Ok(deliver_tokens(mode, jar, tokens))
+}
+
+/// Provisioning JIT: primera vez que vemos esta identidad del IdP.
+/// No se vincula con cuentas locales por email: quien controle el IdP podría
+/// afirmar cualquier email y quedarse con la cuenta.
+async fn provision_oidc_user(state: &AppState, claims: &oidc::IdTokenClaims) -> Result<User, AppError> {
+    let email = claims
+        .email
+        .clone()
+        .ok_or_else(|| AppError::BadRequest("The identity provider did not return an email".to_string()))?;
+
+    // Sin vinculación por email (ver arriba), tampoco podemos crear una segunda cuenta con él
+    if db::email_in_use(&state.pool, &email, None).await? {
+        return Err(AppError::BadRequest(
+            "An account with this email already exists. Log in with it instead".to_string(),
+        ));
+    }
+
+    let base = oidc::username_candidate(claims);
+    let mut username = base.clone();
+    let mut attempt = 1;
+    while db::username_exists(&state.pool, &username).await? {
+        attempt += 1;
+        if attempt > 20 {
+            return Err(AppError::BadRequest("Could not allocate a username".to_string()));
+        }
+        username = format!("{}_{}", base, attempt);
+    }
+
+    let user = db::create_user_with_identity(
+        &state.pool,
+        &username,
+        &email,
+        claims.email_verified,
+        &claims.iss,
+        &claims.sub,
+    )
+    .await?;
+
+    db::grant_role(&state.pool, user.id, "viewer").await?;
+    tracing::info!("Usuario {} provisionado desde {} (sub {})", user.username, claims.iss, claims.sub);
+
+    if !claims.email_verified {
+        send_verification_email(state, user.id, &user.username, &user.email).await?;
+    }
+
+    Ok(user)
 }
 
 // Endpoint temporal para crear usuarios (SOLO PARA DESARROLLO)
@@ -96,12 +839,15 @@ pub async fn login(
 // ============================================================================
 pub async fn register(
     State(state): State<AppState>,
-    Json(payload): Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, AppError> {
+    client: ClientInfo,
+    mode: SessionMode,
+    jar: CookieJar,
+    Json(payload): Json<RegisterRequest>,
+) -> Result<(CookieJar, Json<LoginOutcome>), AppError> {
     // Rate limiting por username
     let rate_key = format!("rate_limit:register:{}", payload.username);
     if !rate_limit::check_rate_limit(&state.redis_client, &rate_key).await? {
-        return Err(AppError::AuthError("Too many requests. Try again later.".to_string()));
+        return Err(AppError::TooManyRequests("Too many requests. Try again later.".to_string()));
     }
 
     // ========================================================================
@@ -113,54 +859,951 @@ pub async fn register(
     let (username, password, email) = UserRegistration::new()
         .username(&payload.username)  // NoUsername -> NoPassword
         .password(&payload.password)  // NoPassword -> Ready
-        .email(format!("{}@test.com", &payload.username))  // Opcional
-        .build();  // Solo Ready tiene .build()
+        .email(payload.email.trim())  // Opcional en el builder, obligatorio en el registro
+        .build()?;  // Solo Ready tiene .build(); Err = errores por campo (422)
     
     // Ahora username y password están GARANTIZADOS por el compilador
-    // No necesitamos Option::unwrap() ni validaciones runtime
+    // y su contenido cumple la política de contraseñas
+
+    let email = email.expect("email configurado arriba");
+    if db::email_in_use(&state.pool, &email, None).await? {
+        return Err(AppError::Validation(vec![db::email_taken()]));
+    }
     
-    let hash = auth::hash_password(&password)?;
+    let hash = auth::hash_password(&password).await?;
     
-    sqlx::query("INSERT INTO users (username, email, password_hash) VALUES ($1, $2, $3)")
+    let user_id = sqlx::query_scalar::<_, i32>(
+        "INSERT INTO users (username, email, password_hash) VALUES ($1, $2, $3) RETURNING id",
+    )
         .bind(&username)
         .bind(&email)
         .bind(&hash)
-        .execute(&state.pool)
-        .await?;
-    
-    let token = auth::create_jwt(&username)?;
-    let refresh_token = auth::create_refresh_token();
-    
-    auth::store_refresh_token(&state.redis_client, &username, &refresh_token).await?;
-    
-    Ok(Json(LoginResponse { access_token: token, refresh_token }))
+        .fetch_one(&state.pool)
+        .await
+        .map_err(db::email_conflict)?;
+
+    // Los usuarios nuevos solo pueden ver el dashboard
+    db::grant_role(&state.pool, user_id, "viewer").await?;
+
+    // El email queda sin verificar hasta que se use el enlace
+    send_verification_email(&state, user_id, &username, &email).await?;
+
+    if auth::email_verification_required() {
+        return Ok((jar, Json(LoginOutcome::EmailVerificationRequired(EmailVerificationRequiredResponse {
+            email_verification_required: true,
+            message: "Check your inbox to verify your email before logging in".to_string(),
+        }))));
+    }
+
+    let tokens = issue_tokens(&state, user_id, &username, &client).await?;
+    Ok(deliver_tokens(mode, jar, tokens))
+}
+
+// --- Passkeys (WebAuthn) ---
+
+/// Opciones para registrar una passkey en la cuenta actual
+pub async fn webauthn_register_start(
+    user: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_interactive_session(&user)?;
+
+    let account = db::get_user_by_id(&state.pool, user.id)
+        .await?
+        .ok_or_else(|| AppError::AuthError("User no longer exists".to_string()))?;
+    let existing = db::get_webauthn_credentials_for_user(&state.pool, user.id).await?;
+
+    let options = webauthn::start_registration(&state.redis_client, &account, &existing).await?;
+    Ok(Json(options))
+}
+
+pub async fn webauthn_register_finish(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<RegistrationCredential>,
+) -> Result<(StatusCode, Json<WebAuthnCredential>), AppError> {
+    require_interactive_session(&user)?;
+
+    let credential = webauthn::finish_registration(&state.redis_client, user.id, &payload).await?;
+    let name = payload
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .unwrap_or("Passkey");
+    if name.chars().count() > 100 {
+        return Err(AppError::BadRequest("name must be at most 100 characters".to_string()));
+    }
+
+    let stored = db::create_webauthn_credential(&state.pool, user.id, &credential, name).await?;
+    tracing::info!("{} ha registrado la passkey {}", user.username, stored.id);
+    Ok((StatusCode::CREATED, Json(stored)))
+}
+
+/// Reto de login. Con username se limitan las credenciales aceptadas a las suyas.
+pub async fn webauthn_login_start(
+    State(state): State<AppState>,
+    payload: Option<Json<PasskeyLoginStartRequest>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let payload = payload.map(|Json(p)| p).unwrap_or_default();
+
+    // Un usuario inexistente recibe un reto sin credenciales: no revela qué cuentas existen
+    let (user_id, credentials) = match payload.username.as_deref() {
+        Some(username) => match db::get_user_by_username(&state.pool, username).await? {
+            Some(user) => (Some(user.id), db::get_webauthn_credentials_for_user(&state.pool, user.id).await?),
+            None => (None, Vec::new()),
+        },
+        None => (None, Vec::new()),
+    };
+
+    let options = webauthn::start_authentication(&state.redis_client, user_id, &credentials).await?;
+    Ok(Json(options))
+}
+
+/// Verifica la firma de la passkey y emite el mismo par de tokens que /login
+pub async fn webauthn_login_finish(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    mode: SessionMode,
+    jar: CookieJar,
+    Json(payload): Json<AssertionCredential>,
+) -> Result<(CookieJar, Json<LoginOutcome>), AppError> {
+    let invalid = || AppError::AuthError("Invalid passkey".to_string());
+
+    let bound_user = webauthn::consume_authentication_challenge(&state.redis_client, &payload).await?;
+
+    let credential = match db::get_webauthn_credential(&state.pool, payload.id.trim_end_matches('=')).await? {
+        Some(credential) if bound_user.is_none_or(|id| id == credential.user_id) => credential,
+        _ => {
+            metrics::record_auth_attempt(false);
+            return Err(invalid());
+        }
+    };
+
+    let sign_count = match webauthn::verify_assertion(&credential, &payload) {
+        Ok(sign_count) => sign_count,
+        Err(e) => {
+            metrics::record_auth_attempt(false);
+            return Err(e);
+        }
+    };
+    db::update_webauthn_sign_count(&state.pool, credential.id, sign_count).await?;
+
+    let user = db::get_user_by_id(&state.pool, credential.user_id).await?.ok_or_else(invalid)?;
+
+    if auth::email_verification_required() && user.email_verified_at.is_none() {
+        return Err(AppError::Forbidden("Email not verified".to_string()));
+    }
+
+    // La passkey con verificación de usuario (PIN/biometría) ya es multifactor: no pedimos TOTP
+    metrics::record_auth_attempt(true);
+    lockout::record_success(&state.redis_client, &user.username, client.ip.as_deref()).await?;
+
+    let tokens = issue_tokens(&state, user.id, &user.username, &client).await?;
+    Ok(deliver_tokens(mode, jar, tokens))
+}
+
+// --- Verificación de email ---
+
+async fn send_verification_email(
+    state: &AppState,
+    user_id: i32,
+    username: &str,
+    email: &str,
+) -> Result<(), AppError> {
+    let token = auth::create_email_verification_token(user_id, email);
+
+    state.mailer.send(mailer::Email {
+        to: email.to_string(),
+        subject: "Verifica tu email".to_string(),
+        body: format!(
+            "Hola {},\n\nConfirma tu dirección de email con este enlace:\n\n{}/verify-email?token={}\n",
+            username, mailer::app_base_url(), token
+        ),
+    }).await
+}
+
+pub async fn verify_email(
+    State(state): State<AppState>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let (user_id, email) = auth::validate_email_verification_token(&query.token)
+        .ok_or_else(|| AppError::BadRequest("Invalid or expired verification link".to_string()))?;
+
+    // Si el usuario cambió de email después de recibir el enlace, ya no vale
+    if !db::mark_email_verified(&state.pool, user_id, &email).await? {
+        return Err(AppError::BadRequest("Invalid or expired verification link".to_string()));
+    }
+
+    Ok(Json(serde_json::json!({ "message": "Email verified" })))
+}
+
+pub async fn resend_verification_email(
+    user: AuthUser,
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    require_interactive_session(&user)?;
+
+    let rate_key = format!("rate_limit:verify_email:{}", user.id);
+    if !rate_limit::check_rate_limit(&state.redis_client, &rate_key).await? {
+        return Err(AppError::TooManyRequests("Too many requests. Try again later.".to_string()));
+    }
+
+    let current = db::get_user_by_id(&state.pool, user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    if current.email_verified_at.is_some() {
+        return Err(AppError::BadRequest("Email already verified".to_string()));
+    }
+
+    send_verification_email(&state, current.id, &current.username, &current.email).await?;
+    Ok((StatusCode::ACCEPTED, Json(serde_json::json!({ "message": "Verification email sent" }))))
 }
 
 pub async fn refresh(
     State(state): State<AppState>,
-    Json(payload): Json<RefreshRequest>,
-) -> Result<Json<LoginResponse>, AppError> {
-    // Validar refresh token
-    let username = auth::validate_refresh_token(&state.redis_client, &payload.refresh_token)
+    client: ClientInfo,
+    headers: HeaderMap,
+    jar: CookieJar,
+    payload: Option<Json<RefreshRequest>>,
+) -> Result<(CookieJar, Json<LoginOutcome>), AppError> {
+    // El refresh token llega en el body (clientes Bearer) o en la cookie HttpOnly
+    // (navegadores); en ese caso la respuesta también va en cookies.
+    let (refresh_token, mode) = match payload {
+        Some(Json(payload)) => (payload.refresh_token, SessionMode::Bearer),
+        None => {
+            let token = jar
+                .get(cookies::REFRESH_COOKIE)
+                .map(|cookie| cookie.value().to_owned())
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| AppError::BadRequest("refresh_token is required".to_string()))?;
+
+            if !cookies::csrf_token_matches(&jar, &headers) {
+                return Err(AppError::Forbidden("Invalid or missing CSRF token".to_string()));
+            }
+            (token, SessionMode::Cookie)
+        }
+    };
+
+    // Rotación: el refresh token presentado se consume y se emite uno nuevo
+    match auth::rotate_refresh_token(&state.redis_client, &refresh_token, &client).await? {
+        auth::RefreshRotation::Rotated { username, session_id, refresh_token } => {
+            // Los roles se releen en cada refresh (al cambiarlos se cortan los access tokens)
+            let user = db::get_user_by_username(&state.pool, &username)
+                .await?
+                .ok_or_else(|| AppError::AuthError("Invalid or expired refresh token".to_string()))?;
+            let roles = db::get_user_roles(&state.pool, user.id).await?;
+            let access_token = auth::create_jwt(&username, &roles, Some(&session_id))?;
+            let tokens = LoginResponse { access_token, refresh_token };
+
+            match mode {
+                SessionMode::Bearer => Ok((jar, Json(LoginOutcome::Tokens(tokens)))),
+                SessionMode::Cookie => {
+                    // El token CSRF se mantiene durante toda la sesión del navegador
+                    let csrf_token = jar
+                        .get(cookies::CSRF_COOKIE)
+                        .map(|cookie| cookie.value().to_owned())
+                        .unwrap_or_default();
+                    let jar = cookies::set_session_cookies(jar, &tokens, &csrf_token);
+                    Ok((jar, Json(LoginOutcome::CookieSession(CookieSessionResponse {
+                        csrf_token,
+                        expires_in: auth::access_token_lifetime_seconds(),
+                    }))))
+                }
+            }
+        }
+        auth::RefreshRotation::Reused => Err(AppError::AuthError(
+            "Refresh token already used. Session revoked, please log in again".to_string(),
+        )),
+        auth::RefreshRotation::Invalid => Err(AppError::AuthError(
+            "Invalid or expired refresh token".to_string(),
+        )),
+    }
+}
+
+// --- Recuperación de contraseña ---
+
+fn password_reset_ttl_minutes() -> i64 {
+    std::env::var("PASSWORD_RESET_TTL_MINUTES")
+        .unwrap_or_else(|_| "30".to_string())
+        .parse()
+        .unwrap_or(30)
+}
+
+/// Envía un enlace de reset. Responde igual exista o no el email (no revela usuarios).
+pub async fn forgot_password(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    // Por email (no inundar un buzón) y por IP (no recorrer listas de emails)
+    let mut rate_keys = vec![format!("rate_limit:password_forgot:{}", payload.email.to_lowercase())];
+    if let Some(ip) = &client.ip {
+        rate_keys.push(format!("rate_limit:password_forgot_ip:{}", ip));
+    }
+    for rate_key in &rate_keys {
+        if !rate_limit::check_rate_limit(&state.redis_client, rate_key).await? {
+            return Err(AppError::TooManyRequests("Too many requests. Try again later.".to_string()));
+        }
+    }
+
+    // En segundo plano: ni el tiempo de respuesta ni un fallo del mailer
+    // revelan si el email tiene cuenta
+    let task_state = state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = send_password_reset(&task_state, &payload.email).await {
+            tracing::error!("No se pudo enviar el enlace de restablecimiento: {:?}", e);
+        }
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "message": "If the email exists, a reset link has been sent" })),
+    ))
+}
+
+async fn send_password_reset(state: &AppState, email: &str) -> Result<(), AppError> {
+    let Some(user) = db::get_user_by_email(&state.pool, email).await? else {
+        return Ok(());
+    };
+    let (token, token_hash) = auth::create_opaque_token();
+    let ttl_minutes = password_reset_ttl_minutes();
+    let expires_at = (chrono::Utc::now() + chrono::Duration::minutes(ttl_minutes)).naive_utc();
+
+    db::create_password_reset_token(&state.pool, user.id, &token_hash, expires_at).await?;
+
+    state.mailer.send(mailer::Email {
+        to: user.email,
+        subject: "Restablecer contraseña".to_string(),
+        body: format!(
+            "Hola {},\n\nPara elegir una nueva contraseña usa este enlace (caduca en {} minutos):\n\n{}/password/reset?token={}\n\nSi no lo has pedido tú, ignora este email.",
+            user.username, ttl_minutes, mailer::app_base_url(), token
+        ),
+    }).await
+}
+
+pub async fn reset_password(
+    State(state): State<AppState>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let invalid = || AppError::BadRequest("Invalid or expired reset token".to_string());
+    let token_hash = auth::hash_opaque_token(&payload.token);
+
+    // Validar la nueva contraseña ANTES de consumir el token, para que un error
+    // de política no obligue a pedir otro enlace
+    let user_id = db::find_password_reset_user(&state.pool, &token_hash).await?.ok_or_else(invalid)?;
+    let user = db::get_user_by_id(&state.pool, user_id).await?.ok_or_else(invalid)?;
+
+    let errors = password_policy::policy().validate("new_password", &user.username, &payload.new_password);
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+
+    // El token es de un solo uso: si otra petición lo consumió antes, fallamos
+    if db::consume_password_reset_token(&state.pool, &token_hash).await? != Some(user.id) {
+        return Err(invalid());
+    }
+
+    let hash = auth::hash_password(&payload.new_password).await?;
+    db::update_password_hash(&state.pool, user.id, &hash).await?;
+
+    // Cualquier sesión abierta con la contraseña anterior deja de valer
+    auth::revoke_all_sessions(&state.redis_client, &user.username).await?;
+    revocation::revoke_tokens_issued_before_now(&state.redis_client, &user.username).await?;
+    tracing::info!("Contraseña restablecida para {}", user.username);
+
+    Ok(Json(serde_json::json!({ "message": "Password has been reset" })))
+}
+
+/// Cambio de contraseña con sesión iniciada. La sesión actual sigue abierta
+/// (con un access token nuevo); todas las demás se cierran.
+pub async fn change_password(
+    user: AuthUser,
+    client: ClientInfo,
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<(CookieJar, Json<PasswordChangedResponse>), AppError> {
+    require_interactive_session(&user)?;
+
+    // Un token robado no debe servir para adivinar la contraseña actual a ciegas
+    let rate_key = format!("rate_limit:password_change:{}", user.id);
+    if !rate_limit::check_rate_limit(&state.redis_client, &rate_key).await? {
+        return Err(AppError::TooManyRequests("Too many requests. Try again later.".to_string()));
+    }
+
+    let account = find_user(&state, user.id).await?;
+    if !auth::verify_password(&payload.current_password, &account.password_hash).await? {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "current_password",
+            "incorrect",
+            "Current password is incorrect",
+        )]));
+    }
+
+    let mut errors = password_policy::policy().validate("new_password", &account.username, &payload.new_password);
+    if payload.new_password == payload.current_password {
+        errors.push(FieldError::new("new_password", "unchanged", "Must be different from the current password"));
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+
+    // Los roles se leen antes de tocar nada: el token nuevo no debe depender de la BBDD
+    let roles = db::get_user_roles(&state.pool, account.id).await?;
+
+    let hash = auth::hash_password(&payload.new_password).await?;
+    db::update_password_hash(&state.pool, account.id, &hash).await?;
+
+    // Las demás sesiones caen; los access tokens emitidos hasta ahora (incluido
+    // el de esta petición) también, así que la sesión actual recibe uno nuevo
+    let session_id = user.claims.as_ref().and_then(|c| c.sid.as_deref());
+    let revoked_sessions = match session_id {
+        Some(session_id) => auth::revoke_other_sessions(&state.redis_client, &account.username, session_id).await?,
+        None => {
+            auth::revoke_all_sessions(&state.redis_client, &account.username).await?;
+            0
+        }
+    };
+    revocation::revoke_tokens_issued_before_now(&state.redis_client, &account.username).await?;
+
+    // Se firma después del corte de revocación para que quede fuera de él
+    let access_token = auth::create_jwt(&account.username, &roles, session_id)?;
+
+    // La contraseña ya ha cambiado y el token de esta petición ya no vale: a
+    // partir de aquí un fallo no puede impedir entregar el token nuevo
+    let event = audit::AuditEvent {
+        actor_id: Some(account.id),
+        subject_id: Some(account.id),
+        action: audit::PASSWORD_CHANGE,
+        ip: client.ip.as_deref(),
+        ..Default::default()
+    };
+    if let Err(e) = audit::record(&state.pool, event).await {
+        tracing::error!("No se pudo auditar el cambio de contraseña de {}: {:?}", account.username, e);
+    }
+    tracing::info!("{} ha cambiado su contraseña ({} sesiones cerradas)", account.username, revoked_sessions);
+
+    let notification = state.mailer.send(mailer::Email {
+        to: account.email,
+        subject: "Tu contraseña ha cambiado".to_string(),
+        body: format!(
+            "Hola {},\n\nLa contraseña de tu cuenta se acaba de cambiar desde {} ({}). Se han cerrado las demás sesiones abiertas.\n\nSi no has sido tú, recupera el acceso cuanto antes en {}/password/forgot y revisa tus sesiones y API keys.",
+            account.username,
+            client.ip.as_deref().unwrap_or("IP desconocida"),
+            client.user_agent.as_deref().unwrap_or("dispositivo desconocido"),
+            mailer::app_base_url(),
+        ),
+    }).await;
+    if let Err(e) = notification {
+        tracing::error!("No se pudo avisar por email del cambio de contraseña de {}: {:?}", account.username, e);
+    }
+
+    // Sesión de navegador: el token nuevo va en la cookie
+    let (jar, access_token) = if jar.get(cookies::ACCESS_COOKIE).is_some() {
+        (cookies::set_access_cookie(jar, &access_token), None)
+    } else {
+        (jar, Some(access_token))
+    };
+
+    Ok((jar, Json(PasswordChangedResponse {
+        message: "Password has been changed".to_string(),
+        revoked_sessions,
+        access_token,
+    })))
+}
+
+/// Cierra la sesión desde la que se hace la petición (la del `sid` del token).
+/// La ruta es pública: sin access token válido (expirado, o cliente que solo
+/// guarda el refresh token) basta con mandar el refresh token en el body.
+pub async fn logout(
+    user: Option<AuthUser>,
+    State(state): State<AppState>,
+    jar: CookieJar,
+    payload: Option<Json<RefreshRequest>>,
+) -> Result<(CookieJar, Json<serde_json::Value>), AppError> {
+    // El access token actual deja de valer ya, no cuando expire
+    if let Some(claims) = user.as_ref().and_then(|u| u.claims.as_ref()) {
+        revocation::revoke_jti(&state.redis_client, &claims.jti, claims.exp).await?;
+    }
+
+    let session_id = user.as_ref().and_then(|u| u.claims.as_ref()).and_then(|c| c.sid.as_deref());
+
+    match (&user, session_id, payload) {
+        (Some(user), Some(session_id), _) => {
+            auth::revoke_session(&state.redis_client, &user.username, session_id).await?;
+        }
+        // Con sesión, el refresh token tiene que ser del mismo usuario; sin ella,
+        // poseer el refresh token ya demuestra que la sesión es de quien llama
+        (_, None, Some(Json(payload))) => {
+            let owner = user.as_ref().map(|u| u.username.as_str());
+            if !auth::revoke_refresh_token(&state.redis_client, &payload.refresh_token, owner).await? {
+                return Err(AppError::AuthError("Invalid or expired refresh token".to_string()));
+            }
+        }
+        _ => {
+            return Err(AppError::BadRequest("No session to log out from".to_string()));
+        }
+    }
+
+    // Sesión de navegador: borrar también las cookies
+    let jar = if jar.get(cookies::ACCESS_COOKIE).is_some() {
+        cookies::clear_session_cookies(jar)
+    } else {
+        jar
+    };
+
+    Ok((jar, Json(serde_json::json!({ "message": "Logged out successfully" }))))
+}
+
+// --- Sesiones ---
+
+pub async fn list_sessions(
+    user: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<SessionResponse>>, AppError> {
+    let current = user.claims.as_ref().and_then(|c| c.sid.as_deref());
+
+    let sessions = auth::list_sessions(&state.redis_client, &user.username)
         .await?
-        .ok_or_else(|| AppError::AuthError("Invalid or expired refresh token".to_string()))?;
+        .into_iter()
+        .map(|(id, session)| SessionResponse {
+            current: current == Some(id.as_str()),
+            id,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: session.created_at,
+            last_refresh_at: session.last_refresh_at,
+        })
+        .collect();
 
-    // Generar nuevo access token
-    let access_token = auth::create_jwt(&username)?;
-    
-    // Mantener el mismo refresh token (o generar uno nuevo si prefieres rotación)
-    Ok(Json(LoginResponse {
+    Ok(Json(sessions))
+}
+
+pub async fn revoke_session(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    require_interactive_session(&user)?;
+
+    if !auth::revoke_session(&state.redis_client, &user.username, &session_id).await? {
+        return Err(AppError::NotFound("Session not found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// "Cerrar sesión en todas partes"
+pub async fn revoke_all_sessions(
+    user: AuthUser,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    require_interactive_session(&user)?;
+
+    auth::revoke_all_sessions(&state.redis_client, &user.username).await?;
+    revocation::revoke_tokens_issued_before_now(&state.redis_client, &user.username).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- Administración de roles (requiere roles:manage) ---
+
+pub async fn get_user_roles(
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+) -> Result<Json<UserRolesResponse>, AppError> {
+    db::get_user_by_id(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    let roles = db::get_user_roles(&state.pool, user_id).await?;
+    Ok(Json(UserRolesResponse { user_id, roles }))
+}
+
+pub async fn grant_role(
+    admin: AuthUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+    Json(payload): Json<RoleRequest>,
+) -> Result<Json<UserRolesResponse>, AppError> {
+    let user = db::get_user_by_id(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    db::grant_role(&state.pool, user_id, &payload.role).await?;
+    // Los permisos viajan en el access token: los emitidos hasta ahora dejan de valer
+    revocation::revoke_tokens_issued_before_now(&state.redis_client, &user.username).await?;
+    tracing::info!("{} concede el rol {} al usuario {}", admin.username, payload.role, user_id);
+
+    let roles = db::get_user_roles(&state.pool, user_id).await?;
+    Ok(Json(UserRolesResponse { user_id, roles }))
+}
+
+pub async fn revoke_role(
+    admin: AuthUser,
+    State(state): State<AppState>,
+    Path((user_id, role)): Path<(i32, String)>,
+) -> Result<StatusCode, AppError> {
+    let user = db::get_user_by_id(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    if !db::revoke_role(&state.pool, user_id, &role).await? {
+        return Err(AppError::NotFound(format!("User {} does not have role '{}'", user_id, role)));
+    }
+
+    // Sin esto conservaría el rol hasta que caducase su access token
+    revocation::revoke_tokens_issued_before_now(&state.redis_client, &user.username).await?;
+    tracing::info!("{} retira el rol {} al usuario {}", admin.username, role, user_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- Revocación de tokens (requiere users:write) ---
+
+pub async fn admin_revoke_token(
+    admin: AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<RevokeTokenRequest>,
+) -> Result<StatusCode, AppError> {
+    let exp = payload.exp.unwrap_or_else(|| {
+        (chrono::Utc::now().timestamp() + auth::access_token_lifetime_seconds()) as usize
+    });
+
+    revocation::revoke_jti(&state.redis_client, &payload.jti, exp).await?;
+    tracing::info!("{} revoca el access token {}", admin.username, payload.jti);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Revoca todas las sesiones y access tokens de un usuario
+pub async fn admin_revoke_user_tokens(
+    admin: AuthUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    let user = db::get_user_by_id(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    auth::revoke_all_sessions(&state.redis_client, &user.username).await?;
+    revocation::revoke_tokens_issued_before_now(&state.redis_client, &user.username).await?;
+
+    tracing::info!("{} revoca todos los tokens de {}", admin.username, user.username);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Levanta el bloqueo por intentos fallidos de una cuenta
+pub async fn admin_unlock_user(
+    admin: AuthUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    let user = db::get_user_by_id(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    lockout::unlock_account(&state.redis_client, &user.username).await?;
+    tracing::info!("{} desbloquea la cuenta {}", admin.username, user.username);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- Suplantación (soporte) ---
+
+/// Token de corta duración para ver la API exactamente como la ve el usuario
+pub async fn start_impersonation(
+    admin: AuthUser,
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Path(user_id): Path<i32>,
+) -> Result<Json<ImpersonationResponse>, AppError> {
+    // Nada de suplantar desde una API key ni encadenar suplantaciones
+    require_interactive_session(&admin)?;
+
+    if user_id == admin.id {
+        return Err(AppError::BadRequest("Cannot impersonate yourself".to_string()));
+    }
+
+    let target = db::get_user_by_id(&state.pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    // Suplantar a otro admin sería una forma de escalar privilegios
+    let roles = db::get_user_roles(&state.pool, target.id).await?;
+    let permissions = db::get_permissions_for_roles(&state.pool, &roles).await?;
+    if permissions.iter().any(|p| p == middleware::IMPERSONATE_PERMISSION) {
+        return Err(AppError::Forbidden("Cannot impersonate another administrator".to_string()));
+    }
+
+    let (access_token, claims) = auth::create_impersonation_jwt(&target.username, &roles, &admin.username)?;
+
+    audit::record(&state.pool, audit::AuditEvent {
+        actor_id: Some(admin.id),
+        subject_id: Some(target.id),
+        action: audit::IMPERSONATION_START,
+        token_id: Some(&claims.jti),
+        ip: client.ip.as_deref(),
+        ..Default::default()
+    })
+    .await?;
+    tracing::warn!("[IMPERSONATION] {} empieza a suplantar a {}", admin.username, target.username);
+
+    Ok(Json(ImpersonationResponse {
         access_token,
-        refresh_token: payload.refresh_token,
+        token_type: "Bearer",
+        expires_in: claims.exp as i64 - claims.iat as i64,
+        impersonating: ImpersonatedUser { id: target.id, username: target.username },
+        actor: admin.username,
     }))
 }
 
-pub async fn logout(
+/// Se llama con el token de suplantación: lo revoca y cierra la auditoría
+pub async fn stop_impersonation(
+    user: AuthUser,
     State(state): State<AppState>,
-    Json(payload): Json<RefreshRequest>,
-) -> Result<Json<serde_json::Value>, AppError> {
-    // Revocar refresh token
-    auth::revoke_refresh_token(&state.redis_client, &payload.refresh_token).await?;
-    
-    Ok(Json(serde_json::json!({ "message": "Logged out successfully" })))
+    client: ClientInfo,
+) -> Result<StatusCode, AppError> {
+    let (Some(actor), Some(claims)) = (&user.impersonator, &user.claims) else {
+        return Err(AppError::BadRequest("Not an impersonation session".to_string()));
+    };
+
+    revocation::revoke_jti(&state.redis_client, &claims.jti, claims.exp).await?;
+
+    audit::record(&state.pool, audit::AuditEvent {
+        actor_id: Some(actor.id),
+        subject_id: Some(user.id),
+        action: audit::IMPERSONATION_STOP,
+        token_id: Some(&claims.jti),
+        ip: client.ip.as_deref(),
+        ..Default::default()
+    })
+    .await?;
+    tracing::warn!("[IMPERSONATION] {} deja de suplantar a {}", actor.username, user.username);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- OAuth2: client_credentials ---
+
+/// POST /oauth/token. Los errores usan los códigos de RFC 6749 §5.2 como mensaje.
+pub async fn oauth_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(payload): Form<TokenRequest>,
+) -> Result<Json<ClientTokenResponse>, AppError> {
+    if payload.grant_type != "client_credentials" {
+        return Err(AppError::BadRequest("unsupported_grant_type".to_string()));
+    }
+
+    // client_secret_basic tiene preferencia sobre client_secret_post
+    let (client_id, secret) = oauth_clients::basic_credentials(&headers)
+        .or_else(|| payload.client_id.clone().zip(payload.client_secret.clone()))
+        .ok_or_else(|| AppError::AuthError("invalid_client".to_string()))?;
+
+    let rate_key = format!("rate_limit:oauth_token:{}", client_id);
+    if !rate_limit::check_rate_limit(&state.redis_client, &rate_key).await? {
+        metrics::record_rate_limit_exceeded("oauth_token");
+        return Err(AppError::TooManyRequests("Too many token requests".to_string()));
+    }
+
+    let client = match db::get_active_client(&state.pool, &client_id).await? {
+        Some(client) if oauth_clients::verify_client_secret(&secret, &client.secret_hash) => client,
+        _ => {
+            metrics::record_auth_attempt(false);
+            return Err(AppError::AuthError("invalid_client".to_string()));
+        }
+    };
+
+    let scopes = oauth_clients::resolve_scopes(payload.scope.as_deref(), &client.scopes)
+        .ok_or_else(|| AppError::BadRequest("invalid_scope".to_string()))?;
+
+    let access_token = auth::create_client_jwt(&client.client_id, &scopes)?;
+    db::touch_client(&state.pool, client.id).await?;
+    metrics::record_auth_attempt(true);
+
+    Ok(Json(ClientTokenResponse {
+        access_token,
+        token_type: "Bearer",
+        expires_in: auth::access_token_lifetime_seconds(),
+        scope: scopes.join(" "),
+    }))
+}
+
+pub async fn create_client(
+    admin: AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateClientRequest>,
+) -> Result<(StatusCode, Json<CreateClientResponse>), AppError> {
+    // Igual que con las API keys: no se concede lo que uno no tiene
+    if let Some(scope) = payload.scopes.iter().find(|s| !admin.has_permission(s)) {
+        return Err(AppError::Forbidden(format!("Cannot grant scope you do not have: {}", scope)));
+    }
+
+    let generated = oauth_clients::generate_client();
+    let client = db::create_client(
+        &state.pool,
+        &generated.client_id,
+        &payload.name,
+        &generated.secret_hash,
+        &payload.scopes,
+        admin.id,
+    )
+    .await?;
+
+    tracing::info!("{} crea el cliente OAuth {} ({})", admin.username, client.client_id, client.name);
+    Ok((StatusCode::CREATED, Json(CreateClientResponse { client, client_secret: generated.secret })))
+}
+
+pub async fn list_clients(State(state): State<AppState>) -> Result<Json<Vec<OAuthClient>>, AppError> {
+    Ok(Json(db::get_clients(&state.pool).await?))
+}
+
+pub async fn revoke_client(
+    admin: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    if !db::revoke_client(&state.pool, id).await? {
+        return Err(AppError::NotFound("Client not found".to_string()));
+    }
+
+    tracing::info!("{} revoca el cliente OAuth {}", admin.username, id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- API Keys ---
+
+pub async fn create_api_key(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreateApiKeyResponse>), AppError> {
+    // Una API key no puede fabricar otras API keys
+    if user.api_key_id.is_some() {
+        return Err(AppError::Forbidden("API keys cannot manage API keys".to_string()));
+    }
+    require_interactive_session(&user)?;
+
+    // Los scopes solo pueden ser permisos que el usuario ya tiene
+    if let Some(scope) = payload.scopes.iter().find(|s| !user.has_permission(s)) {
+        return Err(AppError::Forbidden(format!("Cannot grant scope you do not have: {}", scope)));
+    }
+
+    let expires_at = match payload.expires_in_days {
+        Some(days) if !(1..=api_keys::MAX_EXPIRES_IN_DAYS).contains(&days) => {
+            return Err(AppError::BadRequest(format!(
+                "expires_in_days must be between 1 and {}",
+                api_keys::MAX_EXPIRES_IN_DAYS
+            )));
+        }
+        Some(days) => Some((chrono::Utc::now() + chrono::Duration::days(days)).naive_utc()),
+        None => None,
+    };
+
+    let generated = api_keys::generate_api_key();
+    let api_key = db::create_api_key(
+        &state.pool,
+        user.id,
+        &payload.name,
+        &generated.prefix,
+        &generated.hash,
+        &payload.scopes,
+        expires_at,
+    )
+    .await?;
+
+    tracing::info!("{} crea la API key {} ({})", user.username, api_key.id, api_key.prefix);
+    Ok((StatusCode::CREATED, Json(CreateApiKeyResponse { api_key, key: generated.key })))
+}
+
+pub async fn list_api_keys(
+    user: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ApiKey>>, AppError> {
+    let keys = db::get_api_keys_for_user(&state.pool, user.id).await?;
+    Ok(Json(keys))
+}
+
+pub async fn revoke_api_key(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    if user.api_key_id.is_some() {
+        return Err(AppError::Forbidden("API keys cannot manage API keys".to_string()));
+    }
+    require_interactive_session(&user)?;
+
+    if !db::revoke_api_key(&state.pool, user.id, id).await? {
+        return Err(AppError::NotFound("API key not found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- MFA: enrolamiento TOTP ---
+
+/// Acciones sensibles de la cuenta: ni con API key ni suplantando al usuario
+fn require_interactive_session(user: &AuthUser) -> Result<(), AppError> {
+    if user.api_key_id.is_some() {
+        return Err(AppError::Forbidden("This action requires an interactive session".to_string()));
+    }
+    if let Some(actor) = &user.impersonator {
+        tracing::warn!("[IMPERSONATION] {} intenta una acción bloqueada como {}", actor.username, user.username);
+        return Err(AppError::Forbidden("This action is not allowed while impersonating".to_string()));
+    }
+    Ok(())
+}
+
+/// Paso 1: genera el secreto (pendiente hasta que se confirme con un código)
+pub async fn enroll_totp(
+    user: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<TotpEnrollResponse>, AppError> {
+    require_interactive_session(&user)?;
+
+    let secret = mfa::generate_totp_secret();
+    if !db::upsert_pending_totp(&state.pool, user.id, &secret).await? {
+        return Err(AppError::BadRequest("TOTP is already enabled".to_string()));
+    }
+
+    Ok(Json(TotpEnrollResponse {
+        otpauth_uri: mfa::otpauth_uri(&secret, &user.username),
+        secret,
+    }))
+}
+
+/// Paso 2: el primer código válido activa TOTP y devuelve los códigos de recuperación
+pub async fn confirm_totp(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    require_interactive_session(&user)?;
+
+    let totp = db::get_user_totp(&state.pool, user.id)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("Start TOTP enrollment first".to_string()))?;
+
+    if totp.confirmed_at.is_some() {
+        return Err(AppError::BadRequest("TOTP is already enabled".to_string()));
+    }
+
+    let step = mfa::verify_totp(&totp.secret, &payload.code, totp.last_used_step)
+        .ok_or_else(|| AppError::BadRequest("Invalid TOTP code".to_string()))?;
+
+    if !db::mark_totp_step_used(&state.pool, user.id, step).await? {
+        return Err(AppError::BadRequest("Invalid TOTP code".to_string()));
+    }
+
+    let recovery_codes = mfa::generate_recovery_codes();
+    let hashes: Vec<String> = recovery_codes.iter().map(|c| mfa::hash_recovery_code(c)).collect();
+    db::replace_recovery_codes(&state.pool, user.id, &hashes).await?;
+
+    tracing::info!("{} ha activado TOTP", user.username);
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Claves públicas para que otros servicios validen nuestros access tokens
+pub async fn jwks() -> Json<serde_json::Value> {
+    Json(jwt_keys::keys().jwks())
 }
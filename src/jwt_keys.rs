@@ -0,0 +1,223 @@
+// ============================================================================
+// CLAVES DE FIRMA JWT (HS256 / RS256 / EdDSA) + JWKS
+// ============================================================================
+//
+// Con HS256 todos los servicios que validan nuestros tokens necesitan el
+// secreto... que también sirve para FIRMARLOS. Con claves asimétricas solo la
+// API guarda la clave privada y el resto valida con la pública (vía JWKS).
+//
+// Configuración:
+//   JWT_ALGORITHM            HS256 (default) | RS256 | EdDSA
+//   JWT_SIGNING_KEY_PATH     PEM privado (PKCS#8; PKCS#1 también vale en RS256)
+//   JWT_SIGNING_KEY_ID       kid de la clave activa (se envía en el header)
+//   JWT_VERIFICATION_KEYS    Claves públicas aceptadas: "kid=ruta.pem,kid2=ruta2.pem"
+//
+// Rotación: se publica la clave nueva en JWT_VERIFICATION_KEYS, se cambia la
+// clave de firma y, cuando expiran los tokens antiguos, se retira la vieja.
+//
+// Al arrancar se firma y se verifica un token de prueba con la clave activa:
+// si la privada no corresponde con la pública publicada bajo su kid, la API no
+// arranca (si no, emitiría tokens que nadie puede validar).
+//
+// ============================================================================
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use lazy_static::lazy_static;
+use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts, RsaPublicKey};
+use serde_json::{json, Value};
+use std::env;
+
+/// Prefijo DER (SubjectPublicKeyInfo) de una clave pública Ed25519
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+pub struct SigningKey {
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub key: EncodingKey,
+}
+
+pub struct VerificationKey {
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub key: DecodingKey,
+    /// Representación pública JWK (solo para claves asimétricas)
+    pub jwk: Option<Value>,
+}
+
+pub struct KeyStore {
+    pub signing: SigningKey,
+    pub verification: Vec<VerificationKey>,
+}
+
+lazy_static! {
+    static ref KEYS: KeyStore = KeyStore::from_env().expect("Configuración de claves JWT inválida");
+}
+
+/// Claves cargadas (se leen una sola vez de las variables de entorno)
+pub fn keys() -> &'static KeyStore {
+    &KEYS
+}
+
+impl KeyStore {
+    fn from_env() -> Result<Self, String> {
+        let algorithm = env::var("JWT_ALGORITHM").unwrap_or_else(|_| "HS256".to_string());
+
+        match algorithm.as_str() {
+            "HS256" => Ok(Self::hmac()),
+            "RS256" | "EdDSA" => Self::asymmetric(algorithm.parse().map_err(|_| "algoritmo inválido")?),
+            other => Err(format!("JWT_ALGORITHM no soportado: {}", other)),
+        }
+    }
+
+    fn hmac() -> Self {
+        let secret = env::var("JWT_SECRET")
+            .unwrap_or_else(|_| "fallback_secret_key".to_string())
+            .into_bytes();
+
+        KeyStore {
+            signing: SigningKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                key: EncodingKey::from_secret(&secret),
+            },
+            verification: vec![VerificationKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                key: DecodingKey::from_secret(&secret),
+                jwk: None,
+            }],
+        }
+    }
+
+    fn asymmetric(algorithm: Algorithm) -> Result<Self, String> {
+        let path = env::var("JWT_SIGNING_KEY_PATH")
+            .map_err(|_| "JWT_SIGNING_KEY_PATH es obligatorio con claves asimétricas")?;
+        let kid = env::var("JWT_SIGNING_KEY_ID")
+            .map_err(|_| "JWT_SIGNING_KEY_ID es obligatorio con claves asimétricas")?;
+
+        let pem = read_file(&path)?;
+        let key = match algorithm {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(&pem),
+            _ => EncodingKey::from_ed_pem(&pem),
+        }
+        .map_err(|e| format!("Clave de firma inválida ({}): {}", path, e))?;
+
+        let verification = env::var("JWT_VERIFICATION_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (kid, path) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("Entrada inválida en JWT_VERIFICATION_KEYS: {}", entry))?;
+                load_public_key(kid.trim(), path.trim())
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let published = verification
+            .iter()
+            .find(|k| k.kid.as_deref() == Some(kid.as_str()))
+            .ok_or_else(|| format!("La clave de firma '{}' debe aparecer en JWT_VERIFICATION_KEYS", kid))?;
+
+        let signing = SigningKey { kid: Some(kid), algorithm, key };
+        check_key_pair(&signing, published)?;
+
+        Ok(KeyStore { signing, verification })
+    }
+
+    /// Busca la clave de verificación que corresponde al `kid` del header
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<&VerificationKey> {
+        match kid {
+            Some(kid) => self.verification.iter().find(|k| k.kid.as_deref() == Some(kid)),
+            // Tokens sin kid solo son válidos en modo HS256 (clave única)
+            None => self.verification.iter().find(|k| k.kid.is_none()),
+        }
+    }
+
+    /// Documento JWKS con las claves públicas activas
+    pub fn jwks(&self) -> Value {
+        let keys: Vec<&Value> = self.verification.iter().filter_map(|k| k.jwk.as_ref()).collect();
+        json!({ "keys": keys })
+    }
+}
+
+/// Firma un token de prueba con la clave privada y lo verifica con la pública del mismo kid
+fn check_key_pair(signing: &SigningKey, published: &VerificationKey) -> Result<(), String> {
+    let kid = signing.kid.as_deref().unwrap_or_default();
+    let mut header = Header::new(signing.algorithm);
+    header.kid = signing.kid.clone();
+
+    let exp = chrono::Utc::now().timestamp() + 60;
+    let token = encode(&header, &json!({ "sub": "jwt-keys-probe", "exp": exp }), &signing.key)
+        .map_err(|e| format!("No se pudo firmar con la clave '{}': {}", kid, e))?;
+
+    decode::<Value>(&token, &published.key, &Validation::new(published.algorithm)).map_err(|e| {
+        format!(
+            "La clave de firma '{}' no corresponde con la publicada en JWT_VERIFICATION_KEYS: {}",
+            kid, e
+        )
+    })?;
+    Ok(())
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("No se pudo leer {}: {}", path, e))
+}
+
+fn load_public_key(kid: &str, path: &str) -> Result<VerificationKey, String> {
+    let pem_bytes = read_file(path)?;
+    let pem_str = String::from_utf8_lossy(&pem_bytes);
+
+    // RSA: SubjectPublicKeyInfo ("PUBLIC KEY") o PKCS#1 ("RSA PUBLIC KEY")
+    let rsa_key = RsaPublicKey::from_public_key_pem(&pem_str)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(&pem_str));
+
+    if let Ok(rsa_key) = rsa_key {
+        let key = DecodingKey::from_rsa_pem(&pem_bytes)
+            .map_err(|e| format!("Clave pública RSA inválida ({}): {}", path, e))?;
+
+        return Ok(VerificationKey {
+            kid: Some(kid.to_string()),
+            algorithm: Algorithm::RS256,
+            key,
+            jwk: Some(json!({
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": kid,
+                "n": URL_SAFE_NO_PAD.encode(rsa_key.n().to_bytes_be()),
+                "e": URL_SAFE_NO_PAD.encode(rsa_key.e().to_bytes_be()),
+            })),
+        });
+    }
+
+    // Ed25519: SPKI con la clave pública en los últimos 32 bytes
+    let der = pem::parse(&pem_bytes)
+        .map_err(|e| format!("PEM inválido ({}): {}", path, e))?
+        .into_contents();
+
+    if der.len() != ED25519_SPKI_PREFIX.len() + 32 || !der.starts_with(&ED25519_SPKI_PREFIX) {
+        return Err(format!("Tipo de clave pública no soportado: {}", path));
+    }
+
+    let key = DecodingKey::from_ed_pem(&pem_bytes)
+        .map_err(|e| format!("Clave pública Ed25519 inválida ({}): {}", path, e))?;
+
+    Ok(VerificationKey {
+        kid: Some(kid.to_string()),
+        algorithm: Algorithm::EdDSA,
+        key,
+        jwk: Some(json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "use": "sig",
+            "alg": "EdDSA",
+            "kid": kid,
+            "x": URL_SAFE_NO_PAD.encode(&der[ED25519_SPKI_PREFIX.len()..]),
+        })),
+    })
+}
@@ -0,0 +1,231 @@
+// ============================================================================
+// BLOQUEO PROGRESIVO DE CUENTAS (login throttling)
+// ============================================================================
+//
+// Contamos los fallos de login por (cuenta, IP) y por IP en una ventana de
+// tiempo. Al superar el umbral, esa cuenta DESDE ESA IP (o la IP entera) queda
+// bloqueada un tiempo que se DUPLICA con cada fallo adicional, con un máximo:
+//
+//   fallos:   5    6    7    8   ...
+//   bloqueo: 30s  60s  2m   4m  ... (hasta LOGIN_LOCKOUT_MAX_SECONDS)
+//
+// El bloqueo de cuenta NO es global: si lo fuera, cualquiera que conozca un
+// username podría mantener bloqueado a su dueño fallando a propósito. Los
+// fallos totales de la cuenta se cuentan igualmente, pero solo para alertar
+// (evento `login_account_targeted`), nunca para bloquear.
+//
+// La IP es la de `ClientInfo`: el peer TCP, o X-Forwarded-For solo si llega a
+// través de un proxy de confianza (TRUSTED_PROXIES). Así un cliente no puede
+// esquivar el bloqueo por IP inventándose la cabecera en cada intento.
+//
+// Claves en Redis:
+//   login_failures:user:<username>:ip:<ip> / login_failures:ip:<ip>  -> contador (TTL ventana)
+//   login_lock:user:<username>:ip:<ip>     / login_lock:ip:<ip>      -> bloqueo (TTL = duración)
+//   login_failures:user:<username>    -> fallos totales de la cuenta (solo alertas)
+//   login_locks:user:<username>       -> set con los bloqueos activos (desbloqueo manual)
+//
+// ============================================================================
+
+use redis::AsyncCommands;
+use std::env;
+use crate::{
+    auth::{redis_command_error, redis_connection},
+    error::AppError,
+};
+
+struct LockoutPolicy {
+    threshold: u32,
+    base_seconds: u64,
+    max_seconds: u64,
+    window_seconds: u64,
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+fn account_policy() -> LockoutPolicy {
+    LockoutPolicy {
+        threshold: env_or("LOGIN_LOCKOUT_THRESHOLD", 5),
+        base_seconds: env_or("LOGIN_LOCKOUT_BASE_SECONDS", 30),
+        max_seconds: env_or("LOGIN_LOCKOUT_MAX_SECONDS", 3600),
+        window_seconds: env_or("LOGIN_FAILURE_WINDOW_SECONDS", 900),
+    }
+}
+
+/// Una IP puede probar varias cuentas (NAT, oficinas...): umbral más alto
+fn ip_policy() -> LockoutPolicy {
+    LockoutPolicy {
+        threshold: env_or("LOGIN_IP_LOCKOUT_THRESHOLD", 20),
+        ..account_policy()
+    }
+}
+
+impl LockoutPolicy {
+    /// Duración del bloqueo tras `failures` fallos (0 si no se alcanza el umbral)
+    fn lock_seconds(&self, failures: u32) -> u64 {
+        if failures < self.threshold {
+            return 0;
+        }
+        let exponent = (failures - self.threshold).min(20);
+        self.base_seconds.saturating_mul(1 << exponent).min(self.max_seconds)
+    }
+}
+
+/// Ámbito del bloqueo de cuenta: el username desde una IP concreta
+fn account_scope(username: &str, ip: Option<&str>) -> String {
+    match ip {
+        Some(ip) => format!("user:{}:ip:{}", username, ip),
+        None => format!("user:{}", username),
+    }
+}
+
+/// Segundos restantes de bloqueo para la cuenta (desde esta IP) o la IP (None si puede intentarlo)
+pub async fn locked_for(
+    redis_client: &redis::Client,
+    username: &str,
+    ip: Option<&str>,
+) -> Result<Option<u64>, AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+
+    let user_ttl: i64 = conn
+        .ttl(format!("login_lock:{}", account_scope(username, ip)))
+        .await
+        .map_err(redis_command_error("TTL"))?;
+
+    let ip_ttl: i64 = match ip {
+        Some(ip) => conn
+            .ttl(format!("login_lock:ip:{}", ip))
+            .await
+            .map_err(redis_command_error("TTL"))?,
+        None => -2,
+    };
+
+    // TTL devuelve -2 si la clave no existe
+    let remaining = user_ttl.max(ip_ttl);
+    Ok((remaining > 0).then_some(remaining as u64))
+}
+
+/// Cuenta el fallo en `scope`; devuelve la clave del bloqueo si se ha aplicado
+async fn register_failure(
+    conn: &mut redis::aio::Connection,
+    scope: &str,
+    policy: &LockoutPolicy,
+) -> Result<Option<String>, AppError> {
+    let counter_key = format!("login_failures:{}", scope);
+
+    let failures: u32 = conn.incr(&counter_key, 1).await.map_err(redis_command_error("INCR"))?;
+    if failures == 1 {
+        let _: () = conn
+            .expire(&counter_key, policy.window_seconds as i64)
+            .await
+            .map_err(redis_command_error("EXPIRE"))?;
+    }
+
+    let lock_seconds = policy.lock_seconds(failures);
+    if lock_seconds > 0 {
+        tracing::warn!(
+            security_event = "login_lockout",
+            scope = %scope,
+            failures,
+            lock_seconds,
+            "Bloqueo temporal por intentos fallidos"
+        );
+        let lock_key = format!("login_lock:{}", scope);
+        let _: () = conn
+            .set_ex(&lock_key, failures, lock_seconds)
+            .await
+            .map_err(redis_command_error("SET"))?;
+        crate::metrics::record_rate_limit_exceeded("login");
+        return Ok(Some(lock_key));
+    }
+
+    Ok(None)
+}
+
+/// Fallos totales de la cuenta desde cualquier IP: solo alertan, no bloquean
+async fn track_account_failures(
+    conn: &mut redis::aio::Connection,
+    username: &str,
+    policy: &LockoutPolicy,
+) -> Result<(), AppError> {
+    let counter_key = format!("login_failures:user:{}", username);
+
+    let failures: u32 = conn.incr(&counter_key, 1).await.map_err(redis_command_error("INCR"))?;
+    if failures == 1 {
+        let _: () = conn
+            .expire(&counter_key, policy.window_seconds as i64)
+            .await
+            .map_err(redis_command_error("EXPIRE"))?;
+    }
+
+    // Un aviso cada vez que se completa otro umbral, no uno por fallo
+    if failures >= policy.threshold && failures.is_multiple_of(policy.threshold) {
+        tracing::warn!(
+            security_event = "login_account_targeted",
+            username = %username,
+            failures,
+            "Muchos intentos fallidos contra la cuenta"
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn record_failure(
+    redis_client: &redis::Client,
+    username: &str,
+    ip: Option<&str>,
+) -> Result<(), AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+    let policy = account_policy();
+
+    track_account_failures(&mut conn, username, &policy).await?;
+
+    if let Some(lock_key) = register_failure(&mut conn, &account_scope(username, ip), &policy).await? {
+        // Para que el desbloqueo manual encuentre los bloqueos de todas las IPs
+        let locks_key = format!("login_locks:user:{}", username);
+        let _: () = conn.sadd(&locks_key, &lock_key).await.map_err(redis_command_error("SADD"))?;
+        let _: () = conn
+            .expire(&locks_key, policy.max_seconds as i64)
+            .await
+            .map_err(redis_command_error("EXPIRE"))?;
+    }
+    if let Some(ip) = ip {
+        register_failure(&mut conn, &format!("ip:{}", ip), &ip_policy()).await?;
+    }
+
+    Ok(())
+}
+
+/// Login correcto: se olvidan los fallos de la cuenta desde esa IP (los de la IP
+/// y el total de la cuenta se mantienen)
+pub async fn record_success(
+    redis_client: &redis::Client,
+    username: &str,
+    ip: Option<&str>,
+) -> Result<(), AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+    let _: () = conn
+        .del(format!("login_failures:{}", account_scope(username, ip)))
+        .await
+        .map_err(redis_command_error("DEL"))?;
+    Ok(())
+}
+
+/// Desbloqueo manual (admin): levanta los bloqueos de la cuenta desde todas las IPs
+pub async fn unlock_account(redis_client: &redis::Client, username: &str) -> Result<(), AppError> {
+    let mut conn = redis_connection(redis_client).await?;
+    let locks_key = format!("login_locks:user:{}", username);
+
+    let mut keys: Vec<String> = conn.smembers(&locks_key).await.map_err(redis_command_error("SMEMBERS"))?;
+    // Los contadores van con el mismo ámbito que su bloqueo
+    let counters: Vec<String> = keys.iter().map(|key| key.replacen("login_lock:", "login_failures:", 1)).collect();
+    keys.extend(counters);
+    keys.push(locks_key);
+    keys.push(format!("login_failures:user:{}", username));
+    keys.push(format!("login_lock:user:{}", username));
+
+    let _: () = conn.del(&keys).await.map_err(redis_command_error("DEL"))?;
+    Ok(())
+}
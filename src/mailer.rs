@@ -0,0 +1,82 @@
+// ============================================================================
+// MAILER (envío de emails intercambiable)
+// ============================================================================
+//
+// Los handlers solo conocen el trait `Mailer`. En local usamos `FileMailer`,
+// que escribe cada email como un fichero .eml en una carpeta "outbox" para
+// poder abrir los enlaces sin un servidor SMTP. En producción bastaría con
+// otra implementación (SMTP, SES, ...) elegida en `from_env()`.
+//
+// ============================================================================
+
+use axum::async_trait;
+use std::{env, path::PathBuf, sync::Arc};
+use crate::error::AppError;
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), AppError>;
+}
+
+/// Guarda los emails en disco (MAIL_OUTBOX_DIR, default ./outbox)
+pub struct FileMailer {
+    outbox_dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(outbox_dir: impl Into<PathBuf>) -> Self {
+        FileMailer { outbox_dir: outbox_dir.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), AppError> {
+        let io_error = |e: std::io::Error| {
+            tracing::error!("Mailer error: {}", e);
+            AppError::DatabaseError(sqlx::Error::Protocol("Mailer error".into()))
+        };
+
+        tokio::fs::create_dir_all(&self.outbox_dir).await.map_err(io_error)?;
+
+        let file_name = format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            uuid::Uuid::new_v4()
+        );
+        let content = format!(
+            "To: {}\r\nSubject: {}\r\nDate: {}\r\n\r\n{}\r\n",
+            email.to,
+            email.subject,
+            chrono::Utc::now().to_rfc2822(),
+            email.body
+        );
+
+        tokio::fs::write(self.outbox_dir.join(&file_name), content).await.map_err(io_error)?;
+        tracing::info!("Email para {} guardado en outbox/{}", email.to, file_name);
+        Ok(())
+    }
+}
+
+/// Elige la implementación según MAILER (por ahora solo "file")
+pub fn from_env() -> Arc<dyn Mailer> {
+    match env::var("MAILER").unwrap_or_else(|_| "file".to_string()).as_str() {
+        "file" => {}
+        other => tracing::warn!("MAILER '{}' no soportado, usando 'file'", other),
+    }
+
+    let outbox_dir = env::var("MAIL_OUTBOX_DIR").unwrap_or_else(|_| "outbox".to_string());
+    Arc::new(FileMailer::new(outbox_dir))
+}
+
+/// URL pública de la API para construir enlaces en los emails
+pub fn app_base_url() -> String {
+    env::var("APP_BASE_URL").unwrap_or_else(|_| "http://localhost:3000".to_string())
+}
@@ -1,5 +1,6 @@
 use axum::{
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    routing::{delete, get, post},
     Router,
     middleware as axum_middleware,
 };
@@ -15,11 +16,29 @@ mod handlers;
 mod cache;
 mod auth;
 mod middleware;
+mod extractors;
 mod health;
 mod rate_limit;
 mod builders;
 mod metrics;  // Métricas de Prometheus
 mod metrics_middleware;  // Middleware de métricas HTTP
+mod jwt_keys;  // Claves de firma JWT y JWKS
+mod api_keys;  // API keys para clientes máquina
+mod mfa;  // TOTP y códigos de recuperación
+mod mailer;  // Envío de emails (outbox en local)
+mod revocation;  // Denylist de access tokens (jti)
+mod lockout;  // Bloqueo progresivo tras logins fallidos
+mod password;  // Hashing de contraseñas (Argon2id / bcrypt)
+mod oidc;  // Login con OpenID Connect (Authorization Code + PKCE)
+mod oauth_clients;  // Clientes OAuth2 (client_credentials) para servicios
+mod cookies;  // Modo cookie (HttpOnly) para navegadores + CSRF
+mod audit;  // Registro de auditoría (suplantación)
+mod webauthn;  // Passkeys (WebAuthn): registro y login sin contraseña
+mod pagination;  // Paginación por cursor (keyset) reutilizable
+mod visibility;  // Campos privados (PII) según quién pregunta
+mod erasure;  // Borrado de cuentas (RGPD) y job de purga
+mod storage;  // Ficheros subidos (disco en local, intercambiable)
+mod avatars;  // Validación y miniaturas de avatares
 
 #[tokio::main]
 async fn main() {
@@ -51,6 +70,11 @@ async fn main() {
         .await
         .expect("Fallo de migración");
 
+    // Cargar claves JWT al arrancar (falla rápido si la configuración es inválida)
+    tracing::info!("JWT signing algorithm: {:?}", jwt_keys::keys().signing.algorithm);
+    let _ = auth::verify_login_password("warm-up", None).await; // Calcula los hashes señuelo antes del primer login
+    auth::check_link_secrets(); // Sin secretos de firma no se pueden enviar enlaces seguros
+
     // 2. Conectar a Redis
     let redis_url = env::var("REDIS_URL")
         .unwrap_or_else(|_| "redis://host.docker.internal/".to_string());
@@ -58,14 +82,111 @@ async fn main() {
     tracing::info!("Connecting to Redis at: {}", redis_url);
     let redis_client = redis::Client::open(redis_url.as_str()).expect("Error creando cliente Redis");
 
+    let storage = storage::from_env();
+
+    // Purga en segundo plano de las cuentas borradas (RGPD)
+    erasure::spawn_purge_job(pool.clone(), storage.clone());
+
     let shared_state = AppState {
         pool,
         redis_client,
+        mailer: mailer::from_env(),
+        storage,
     };
 
     // 3. Router
     let protected_routes = Router::new()
-        .route("/dashboard", get(handlers::get_dashboard))
+        .route(
+            "/dashboard",
+            get(handlers::get_dashboard)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("dashboard:read"))),
+        )
+        .route(
+            "/dashboard/activities",
+            get(handlers::list_activities)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("dashboard:read"))),
+        )
+        .route(
+            "/dashboard/alerts",
+            get(handlers::list_alerts)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("dashboard:read"))),
+        )
+        .route(
+            "/admin/users/:id/roles",
+            get(handlers::get_user_roles)
+                .post(handlers::grant_role)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("roles:manage"))),
+        )
+        .route(
+            "/admin/users/:id/roles/:role",
+            delete(handlers::revoke_role)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("roles:manage"))),
+        )
+        .route(
+            "/admin/tokens/revoke",
+            post(handlers::admin_revoke_token)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("users:write"))),
+        )
+        .route(
+            "/admin/users/:id/revoke-tokens",
+            post(handlers::admin_revoke_user_tokens)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("users:write"))),
+        )
+        .route(
+            "/admin/users/:id/unlock",
+            post(handlers::admin_unlock_user)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("users:write"))),
+        )
+        .route(
+            "/admin/clients",
+            get(handlers::list_clients)
+                .post(handlers::create_client)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("clients:manage"))),
+        )
+        .route(
+            "/admin/clients/:id",
+            delete(handlers::revoke_client)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("clients:manage"))),
+        )
+        .route(
+            "/admin/impersonate/:user_id",
+            post(handlers::start_impersonation)
+                .route_layer(axum_middleware::from_fn(middleware::require_permission("users:impersonate"))),
+        )
+        // Se llama con el token de suplantación, que no lleva los permisos del admin:
+        // basta con que el token tenga claim `act` (el permiso solo se pide al empezar)
+        .route(
+            "/admin/impersonate/stop",
+            post(handlers::stop_impersonation)
+                .route_layer(axum_middleware::from_fn(middleware::require_impersonation)),
+        )
+        .route("/me", get(handlers::get_me).delete(handlers::delete_me))
+        .route("/me/export", get(handlers::export_me))
+        .route("/me/password", post(handlers::change_password))
+        .route(
+            "/me/avatar",
+            post(handlers::upload_avatar)
+                .delete(handlers::delete_avatar)
+                // El límite por defecto de axum (2 MB) es menor que el de avatares; margen para el multipart
+                .layer(DefaultBodyLimit::max(avatars::max_upload_bytes() + 64 * 1024)),
+        )
+        .route("/users", get(handlers::list_users))
+        .route(
+            "/users/:id",
+            get(handlers::get_user)
+                .patch(handlers::update_user)
+                .delete(handlers::delete_user),
+        )
+        .route("/api-keys", get(handlers::list_api_keys).post(handlers::create_api_key))
+        .route("/api-keys/:id", delete(handlers::revoke_api_key))
+        .route("/mfa/totp/enroll", post(handlers::enroll_totp))
+        .route("/mfa/totp/confirm", post(handlers::confirm_totp))
+        .route("/webauthn/register/start", post(handlers::webauthn_register_start))
+        .route("/webauthn/register/finish", post(handlers::webauthn_register_finish))
+        .route("/verify-email/resend", post(handlers::resend_verification_email))
+        .route("/sessions", get(handlers::list_sessions))
+        .route("/sessions/revoke-all", post(handlers::revoke_all_sessions))
+        .route("/sessions/:id", delete(handlers::revoke_session))
         .layer(axum::middleware::from_fn_with_state(shared_state.clone(), middleware::auth_middleware));
 
     let app = Router::new()
@@ -73,20 +194,40 @@ async fn main() {
         .route("/", get(root))
         .route("/health", get(health::health_check))
         .route("/metrics", get(metrics_handler))  // Endpoint de métricas
-        .route("/users", get(handlers::list_users))
         .route("/login", post(handlers::login))
+        .route("/login/mfa", post(handlers::login_mfa))
+        .route("/login/magic", post(handlers::request_magic_link))
+        .route("/login/magic/verify", get(handlers::magic_link_landing).post(handlers::verify_magic_link))
         .route("/register", post(handlers::register))
+        .route("/webauthn/login/start", post(handlers::webauthn_login_start))
+        .route("/webauthn/login/finish", post(handlers::webauthn_login_finish))
         .route("/refresh", post(handlers::refresh))
+        .route(
+            "/logout",
+            post(handlers::logout)
+                .route_layer(axum::middleware::from_fn_with_state(shared_state.clone(), middleware::optional_auth_middleware)),
+        )
+        .route("/verify-email", get(handlers::verify_email))
+        .route("/password/forgot", post(handlers::forgot_password))
+        .route("/password/reset", post(handlers::reset_password))
+        .route("/auth/oidc/login", get(handlers::oidc_login))
+        .route("/auth/oidc/callback", get(handlers::oidc_callback))
+        .route("/oauth/token", post(handlers::oauth_token))
+        .route("/.well-known/jwks.json", get(handlers::jwks))
+        .route("/media/*key", get(handlers::serve_media))
         .layer(axum_middleware::from_fn(metrics_middleware::metrics_middleware))  // Métricas automáticas
-        .route("/logout", post(handlers::logout))
         .with_state(shared_state);
 
     // 4. Server
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    let port: u16 = env::var("PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(3000);
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
     tracing::info!("Server listening on http://{}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // ConnectInfo: IP del cliente para el registro de sesiones
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
 }
 
 async fn root() -> &'static str {
@@ -9,14 +9,15 @@
 // - http_request_duration_seconds: Latencia de requests
 // - cache_hits_total / cache_misses_total: Performance del cache
 // - auth_attempts_total: Intentos de autenticación
+// - api_key_requests_total: Requests de clientes máquina (API keys)
 // - rate_limit_exceeded_total: Rate limiting triggers
 //
 // ============================================================================
 
 use lazy_static::lazy_static;
 use prometheus::{
-    register_counter_vec, register_histogram_vec, register_int_counter_vec, CounterVec,
-    Encoder, HistogramVec, IntCounterVec, TextEncoder,
+    register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec,
+    TextEncoder,
 };
 
 // ============================================================================
@@ -71,6 +72,13 @@ lazy_static! {
     )
     .unwrap();
 
+    pub static ref API_KEY_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "api_key_requests_total",
+        "Total requests authenticated with an API key",
+        &["result"] // success, invalid, rate_limited
+    )
+    .unwrap();
+
     // Rate Limiting
     pub static ref RATE_LIMIT_EXCEEDED: IntCounterVec = register_int_counter_vec!(
         "rate_limit_exceeded_total",
@@ -78,15 +86,6 @@ lazy_static! {
         &["endpoint"]
     )
     .unwrap();
-
-    // Database Metrics
-    pub static ref DB_QUERY_DURATION: HistogramVec = register_histogram_vec!(
-        "db_query_duration_seconds",
-        "Database query duration in seconds",
-        &["query_type"],
-        vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
-    )
-    .unwrap();
 }
 
 // ============================================================================
@@ -134,14 +133,12 @@ pub fn record_jwt_issued(token_type: &str) {
     JWT_TOKENS_ISSUED.with_label_values(&[token_type]).inc();
 }
 
+/// Registra un request autenticado con API key
+pub fn record_api_key_request(result: &str) {
+    API_KEY_REQUESTS.with_label_values(&[result]).inc();
+}
+
 /// Registra un rate limit excedido
 pub fn record_rate_limit_exceeded(endpoint: &str) {
     RATE_LIMIT_EXCEEDED.with_label_values(&[endpoint]).inc();
 }
-
-/// Registra duración de query a DB
-pub fn record_db_query(query_type: &str, duration: f64) {
-    DB_QUERY_DURATION
-        .with_label_values(&[query_type])
-        .observe(duration);
-}
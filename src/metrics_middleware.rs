@@ -8,9 +8,7 @@
 // ============================================================================
 
 use axum::{
-    body::Body,
     extract::Request,
-    http::StatusCode,
     middleware::Next,
     response::Response,
 };
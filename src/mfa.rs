@@ -0,0 +1,98 @@
+// ============================================================================
+// MFA: TOTP (RFC 6238) + códigos de recuperación
+// ============================================================================
+//
+// TOTP = HOTP(secreto, floor(unix_time / 30)) truncado a 6 dígitos (RFC 4226).
+// Aceptamos el intervalo actual y el anterior/siguiente para tolerar relojes
+// algo desincronizados, y nunca un intervalo igual o anterior al último usado.
+//
+// ============================================================================
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::env;
+
+const TOTP_PERIOD_SECONDS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+const TOTP_ALLOWED_DRIFT_STEPS: i64 = 1;
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Secreto aleatorio de 160 bits en Base32 (lo que esperan las apps autenticadoras)
+pub fn generate_totp_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// URI para el QR: otpauth://totp/Issuer:username?secret=...&issuer=...
+pub fn otpauth_uri(secret: &str, username: &str) -> String {
+    let issuer = env::var("MFA_ISSUER").unwrap_or_else(|_| "RustAPI".to_string());
+    let encode = |value: &str| url::form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode(&issuer),
+        encode(username),
+        secret,
+        encode(&issuer),
+        TOTP_DIGITS,
+        TOTP_PERIOD_SECONDS
+    )
+}
+
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC acepta claves de cualquier tamaño");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation (RFC 4226 §5.3)
+    let offset = (digest[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]])
+        & 0x7fff_ffff;
+
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Comprueba un código TOTP y devuelve el intervalo (step) que ha encajado.
+///
+/// `last_used_step` evita aceptar dos veces el mismo código (replay).
+pub fn verify_totp(secret_b32: &str, code: &str, last_used_step: Option<i64>) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret_b32.as_bytes()).ok()?;
+    // Exactamente TOTP_DIGITS cifras: "12345" o "+123456" no son códigos válidos
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current_step = chrono::Utc::now().timestamp() / TOTP_PERIOD_SECONDS;
+
+    (-TOTP_ALLOWED_DRIFT_STEPS..=TOTP_ALLOWED_DRIFT_STEPS)
+        .map(|drift| current_step + drift)
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| hotp(&secret, *step as u64) == code)
+}
+
+/// Genera códigos de recuperación legibles: xxxxx-xxxxx
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10)
+                .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+/// Los códigos tienen ~50 bits de entropía: SHA-256 basta y permite buscar por hash
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized = code.trim().to_lowercase();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
@@ -1,36 +1,293 @@
 use axum::{
-    extract::Request,
-    http::{StatusCode, header},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method},
     middleware::Next,
     response::Response,
 };
-use crate::auth;
+use axum_extra::extract::cookie::CookieJar;
+use std::{future::Future, pin::Pin};
+use crate::{
+    api_keys, audit, auth, cookies, db, error::AppError,
+    extractors::{AuthUser, ClientInfo, Impersonator, Principal, ServicePrincipal},
+    metrics, models::AppState, rate_limit, revocation,
+};
+
+pub const IMPERSONATE_PERMISSION: &str = "users:impersonate";
 
 pub async fn auth_middleware(
-    request: Request,
+    State(state): State<AppState>,
+    mut request: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
-    // 1. Buscar header Authorization
-    let auth_header = request.headers()
-        .get(header::AUTHORIZATION)
+) -> Result<Response, AppError> {
+    // Clientes máquina: X-API-Key. Usuarios: Authorization: Bearer <jwt>
+    let api_key = request.headers()
+        .get(api_keys::API_KEY_HEADER)
+        .and_then(|header| header.to_str().ok());
+
+    let principal = match api_key {
+        Some(key) => Principal::User(authenticate_api_key(&state, key).await?),
+        None => authenticate_bearer(&state, request.headers(), request.method()).await?,
+    };
+
+    // Sesión suplantada: se audita la request y se marca la respuesta
+    let impersonation = match &principal {
+        Principal::User(user) => user.impersonator.clone().map(|actor| (actor, user.clone())),
+        Principal::Service(_) => None,
+    };
+
+    // Inyectar el principal en las extensions para los extractores AuthUser / Principal
+    request.extensions_mut().insert(principal);
+
+    let Some((actor, user)) = impersonation else {
+        return Ok(next.run(request).await);
+    };
+
+    let method = request.method().to_string();
+    let path = request.uri().path().to_owned();
+    let client = ClientInfo::from_parts(request.headers(), request.extensions());
+
+    let mut response = next.run(request).await;
+
+    tracing::info!(
+        "[IMPERSONATION] {} como {}: {} {} -> {}",
+        actor.username, user.username, method, path, response.status()
+    );
+    if let Ok(value) = HeaderValue::from_str(&actor.username) {
+        response.headers_mut().insert("x-impersonated-by", value);
+    }
+
+    let event = audit::AuditEvent {
+        actor_id: Some(actor.id),
+        subject_id: Some(user.id),
+        action: audit::IMPERSONATION_REQUEST,
+        method: Some(&method),
+        path: Some(&path),
+        status: Some(response.status().as_u16()),
+        token_id: user.claims.as_ref().map(|c| c.jti.as_str()),
+        ip: client.ip.as_deref(),
+    };
+    if let Err(e) = audit::record(&state.pool, event).await {
+        tracing::error!("No se pudo auditar la request suplantada {} {}: {:?}", method, path, e);
+    }
+
+    Ok(response)
+}
+
+/// Para rutas públicas que se comportan distinto con sesión (ej. /logout): si
+/// llega un token válido se inyecta el principal; si falta o no vale (expirado,
+/// revocado...) la request sigue como anónima en vez de responder 401.
+pub async fn optional_auth_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let api_key = request.headers()
+        .get(api_keys::API_KEY_HEADER)
         .and_then(|header| header.to_str().ok());
+    let has_bearer = request.headers().contains_key(header::AUTHORIZATION)
+        || CookieJar::from_headers(request.headers()).get(cookies::ACCESS_COOKIE).is_some();
+
+    let principal = match api_key {
+        Some(key) => authenticate_api_key(&state, key).await.map(Principal::User).ok(),
+        None if has_bearer => authenticate_bearer(&state, request.headers(), request.method()).await.ok(),
+        None => None,
+    };
+    if let Some(principal) = principal {
+        request.extensions_mut().insert(principal);
+    }
+
+    next.run(request).await
+}
+
+async fn authenticate_bearer(
+    state: &AppState,
+    headers: &HeaderMap,
+    method: &Method,
+) -> Result<Principal, AppError> {
+    // 1. Buscar header Authorization con formato "Bearer <token>" o, en modo
+    //    navegador, la cookie access_token
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "));
 
-    match auth_header {
-        Some(auth_header) => {
-            // Esperamos formato "Bearer <token>"
-            if let Some(token) = auth_header.strip_prefix("Bearer ") {
-                // 2. Validar Token
-                match auth::validate_jwt(token) {
-                    Ok(_token_data) => {
-                        // TODO: Podríamos inyectar el usuario en la request extensions aquí
-                        Ok(next.run(request).await)
-                    },
-                    Err(_) => Err(StatusCode::UNAUTHORIZED),
-                }
-            } else {
-                Err(StatusCode::UNAUTHORIZED)
+    let jar = CookieJar::from_headers(headers);
+    let token = match bearer {
+        Some(token) => token.to_owned(),
+        None => {
+            let cookie = jar
+                .get(cookies::ACCESS_COOKIE)
+                .map(|cookie| cookie.value().to_owned())
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| AppError::AuthError("Missing bearer token".to_string()))?;
+
+            // El navegador adjunta la cookie solo: exigir el token CSRF (double-submit)
+            if !cookies::is_safe_method(method) && !cookies::csrf_token_matches(&jar, headers) {
+                return Err(AppError::Forbidden("Invalid or missing CSRF token".to_string()));
             }
-        },
-        None => Err(StatusCode::UNAUTHORIZED),
+            cookie
+        }
+    };
+    let token = token.as_str();
+
+    // 2. Validar Token
+    let token_data = auth::validate_jwt(token)
+        .map_err(|_| AppError::AuthError("Invalid or expired token".to_string()))?;
+
+    // 3. Comprobar que no ha sido revocado (logout, cambio de contraseña, admin...)
+    if revocation::is_revoked(&state.redis_client, &token_data.claims).await? {
+        return Err(AppError::AuthError("Token has been revoked".to_string()));
+    }
+
+    // 4. Tokens de servicio: no hay usuario detrás
+    if let Some(client_id) = token_data.claims.client_id.clone() {
+        return authenticate_service(state, client_id, token_data.claims).await;
     }
+
+    // 5. Cargar el usuario (el token puede sobrevivir a un usuario borrado)
+    let user = db::get_user_by_username(&state.pool, &token_data.claims.sub)
+        .await?
+        .ok_or_else(|| AppError::AuthError("Invalid or expired token".to_string()))?;
+
+    // 6. Resolver los permisos de los roles que lleva el token
+    let permissions = db::get_permissions_for_roles(&state.pool, &token_data.claims.roles).await?;
+
+    // 7. Token de suplantación: el admin tiene que seguir pudiendo suplantar
+    let impersonator = match &token_data.claims.act {
+        Some(actor) => Some(authenticate_impersonator(state, &actor.sub).await?),
+        None => None,
+    };
+
+    Ok(Principal::User(AuthUser {
+        id: user.id,
+        username: user.username,
+        claims: Some(token_data.claims),
+        permissions,
+        api_key_id: None,
+        impersonator,
+    }))
+}
+
+async fn authenticate_impersonator(state: &AppState, username: &str) -> Result<Impersonator, AppError> {
+    let denied = || AppError::AuthError("Impersonation is no longer allowed".to_string());
+
+    let actor = db::get_user_by_username(&state.pool, username).await?.ok_or_else(denied)?;
+    let roles = db::get_user_roles(&state.pool, actor.id).await?;
+    let permissions = db::get_permissions_for_roles(&state.pool, &roles).await?;
+
+    if !permissions.iter().any(|p| p == IMPERSONATE_PERMISSION) {
+        return Err(denied());
+    }
+
+    Ok(Impersonator { id: actor.id, username: actor.username })
+}
+
+async fn authenticate_service(
+    state: &AppState,
+    client_id: String,
+    claims: crate::models::Claims,
+) -> Result<Principal, AppError> {
+    // Un cliente revocado pierde el acceso aunque sus tokens no hayan expirado
+    let client = db::get_active_client(&state.pool, &client_id)
+        .await?
+        .ok_or_else(|| AppError::AuthError("Invalid or expired token".to_string()))?;
+
+    // Como con las API keys: nunca más de lo que el cliente tiene concedido AHORA
+    let permissions = claims
+        .scope
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter(|scope| client.scopes.iter().any(|s| s == scope))
+        .map(str::to_owned)
+        .collect();
+
+    Ok(Principal::Service(ServicePrincipal {
+        id: client.id,
+        client_id: client.client_id,
+        name: client.name,
+        claims,
+        permissions,
+    }))
+}
+
+async fn authenticate_api_key(state: &AppState, key: &str) -> Result<AuthUser, AppError> {
+    let Some(api_key) = db::get_active_api_key_by_hash(&state.pool, &api_keys::hash_api_key(key)).await? else {
+        metrics::record_api_key_request("invalid");
+        return Err(AppError::AuthError("Invalid or expired API key".to_string()));
+    };
+
+    // Rate limit propio por clave, independiente del de /login
+    let rate_key = format!("rate_limit:api_key:{}", api_key.id);
+    if !rate_limit::check_rate_limit(&state.redis_client, &rate_key).await? {
+        metrics::record_api_key_request("rate_limited");
+        metrics::record_rate_limit_exceeded("api_key");
+        return Err(AppError::TooManyRequests("API key rate limit exceeded".to_string()));
+    }
+
+    let user = db::get_user_by_id(&state.pool, api_key.user_id)
+        .await?
+        .ok_or_else(|| AppError::AuthError("Invalid or expired API key".to_string()))?;
+
+    // Los scopes nunca dan más de lo que el dueño tiene AHORA (si pierde un rol, la clave también)
+    let roles = db::get_user_roles(&state.pool, user.id).await?;
+    let permissions = db::get_permissions_for_roles(&state.pool, &roles)
+        .await?
+        .into_iter()
+        .filter(|p| api_key.scopes.contains(p))
+        .collect();
+
+    db::touch_api_key(&state.pool, api_key.id).await?;
+    metrics::record_api_key_request("success");
+
+    Ok(AuthUser {
+        id: user.id,
+        username: user.username,
+        claims: None,
+        permissions,
+        api_key_id: Some(api_key.id),
+        impersonator: None,
+    })
+}
+
+type MiddlewareFuture = Pin<Box<dyn Future<Output = Result<Response, AppError>> + Send>>;
+
+/// Layer por ruta que exige un permiso concreto. Debe ir DENTRO de auth_middleware:
+///
+///   .route("/dashboard", get(handler).route_layer(
+///       axum_middleware::from_fn(middleware::require_permission("dashboard:read"))))
+pub fn require_permission(
+    permission: &'static str,
+) -> impl Fn(Request, Next) -> MiddlewareFuture + Clone + Send + 'static {
+    move |request: Request, next: Next| {
+        Box::pin(async move {
+            let principal = request
+                .extensions()
+                .get::<Principal>()
+                .ok_or_else(|| AppError::AuthError("Authentication required".to_string()))?;
+
+            if !principal.has_permission(permission) {
+                tracing::warn!("{} no tiene el permiso {}", principal.name(), permission);
+                return Err(AppError::Forbidden(format!("Missing permission: {}", permission)));
+            }
+
+            Ok(next.run(request).await)
+        })
+    }
+}
+
+/// Layer para las rutas que solo tienen sentido dentro de una suplantación
+/// (token con claim `act`). Debe ir DENTRO de auth_middleware. No pide ningún permiso: el token suplantado lleva
+/// los del usuario, no los del admin, y terminar la sesión nunca da más acceso.
+pub async fn require_impersonation(request: Request, next: Next) -> Result<Response, AppError> {
+    let impersonated = matches!(
+        request.extensions().get::<Principal>(),
+        Some(Principal::User(AuthUser { claims: Some(claims), .. })) if claims.act.is_some()
+    );
+
+    if !impersonated {
+        return Err(AppError::BadRequest("Not an impersonation session".to_string()));
+    }
+
+    Ok(next.run(request).await)
 }
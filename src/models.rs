@@ -1,5 +1,8 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Serialize, Deserialize};
 use sqlx::{FromRow, PgPool};
+use std::sync::Arc;
+use crate::{mailer::Mailer, storage::Storage, visibility::FieldPolicy};
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct User {
@@ -8,6 +11,31 @@ pub struct User {
     pub email: String,
     #[serde(skip)] // No queremos enviar el hash en el JSON de respuesta
     pub password_hash: String,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+// Perfil público: id, username, display_name, bio y avatar. El resto, solo dueño y admins.
+impl FieldPolicy for User {
+    const PRIVATE_FIELDS: &'static [&'static str] = &["email", "email_verified_at", "timezone", "locale"];
+
+    fn owner_id(&self) -> Option<i32> {
+        Some(self.id)
+    }
+}
+
+/// PATCH /users/:id: solo se tocan los campos presentes. "" borra los opcionales.
+#[derive(Debug, Deserialize)]
+pub struct UpdateUserRequest {
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -16,21 +44,398 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub password: String,
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+/// GET /users?limit=&after=&sort=-username&username_prefix=&email_domain=
+#[derive(Debug, Deserialize)]
+pub struct ListUsersQuery {
+    pub limit: Option<i64>,
+    pub after: Option<String>,
+    pub sort: Option<String>,
+    pub username_prefix: Option<String>,
+    pub email_domain: Option<String>,
+}
+
+/// Listados paginados del dashboard (actividad y alertas)
+#[derive(Debug, Deserialize)]
+pub struct DashboardListQuery {
+    pub limit: Option<i64>,
+    pub after: Option<String>,
+    pub sort: Option<String>,
+    pub severity: Option<String>, // Solo alertas
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkVerifyRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcLoginQuery {
+    pub login_hint: Option<String>,
+    /// "cookie" para recibir la sesión en cookies HttpOnly al volver del IdP
+    pub session_mode: Option<String>,
+}
+
+/// Parámetros con los que el IdP vuelve a /auth/oidc/callback
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     pub access_token: String,
     pub refresh_token: String,
 }
 
+/// Respuesta de /login y /register: tokens, un reto MFA si el usuario tiene TOTP
+/// activo, o el aviso de que falta verificar el email
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginOutcome {
+    Tokens(LoginResponse),
+    CookieSession(CookieSessionResponse),
+    MfaRequired(MfaChallengeResponse),
+    EmailVerificationRequired(EmailVerificationRequiredResponse),
+}
+
+/// Respuesta en modo cookie: los tokens viajan en cookies HttpOnly, nunca en el body
+#[derive(Debug, Serialize)]
+pub struct CookieSessionResponse {
+    pub csrf_token: String,
+    pub expires_in: i64, // Vida del access token, para saber cuándo llamar a /refresh
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmailVerificationRequiredResponse {
+    pub email_verification_required: bool,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct UserTotp {
+    pub secret: String,
+    pub confirmed_at: Option<NaiveDateTime>,
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>, // Solo se muestran una vez
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PasswordChangedResponse {
+    pub message: String,
+    pub revoked_sessions: usize,
+    /// Nuevo access token de la sesión actual (el anterior queda revocado).
+    /// En sesiones con cookies va en la cookie, no aquí.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RefreshRequest {
     pub refresh_token: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String, // Subject (Username)
     pub exp: usize,  // Expiration time
+    pub iat: usize,  // Issued at
+    pub jti: String, // Id único del token (denylist de revocación)
+    #[serde(default)]
+    pub roles: Vec<String>, // Roles del usuario en el momento de emitir el token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>, // Sesión (familia de refresh tokens) que emitió el token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>, // Solo en tokens de servicio (client_credentials)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>, // Scopes de un token de servicio, separados por espacios
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<ActorClaim>, // Suplantación: quién actúa realmente (RFC 8693)
+}
+
+/// Claim `act`: el admin que está usando el token en nombre de `sub`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ActorClaim {
+    pub sub: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImpersonatedUser {
+    pub id: i32,
+    pub username: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImpersonationResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    pub impersonating: ImpersonatedUser,
+    pub actor: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleRequest {
+    pub role: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserRolesResponse {
+    pub user_id: i32,
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateApiKeyResponse {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String, // Solo se devuelve en la creación
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct WebAuthnCredential {
+    pub id: i32,
+    pub user_id: i32,
+    pub credential_id: String,
+    #[serde(skip)]
+    pub public_key: Vec<u8>,
+    #[serde(skip)]
+    pub algorithm: i32,
+    #[serde(skip)]
+    pub sign_count: i64,
+    pub transports: Vec<String>,
+    pub name: String,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+/// PublicKeyCredential de navigator.credentials.create(), con binarios en base64url
+#[derive(Debug, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+    pub name: Option<String>, // Nombre para reconocer la passkey en el listado
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+/// PublicKeyCredential de navigator.credentials.get(), con binarios en base64url
+#[derive(Debug, Deserialize)]
+pub struct AssertionCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(rename = "userHandle")]
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct PasskeyLoginStartRequest {
+    pub username: Option<String>, // Sin username: passkeys descubribles
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct OAuthClient {
+    pub id: i32,
+    pub client_id: String,
+    pub name: String,
+    #[serde(skip)]
+    pub secret_hash: String,
+    pub scopes: Vec<String>,
+    pub created_by: Option<i32>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateClientRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateClientResponse {
+    #[serde(flatten)]
+    pub client: OAuthClient,
+    pub client_secret: String, // Solo se devuelve en la creación
+}
+
+/// Formulario de POST /oauth/token
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientTokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    pub scope: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeTokenRequest {
+    pub jti: String,
+    pub exp: Option<usize>, // Si no se conoce, se asume la vida máxima de un access token
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_refresh_at: DateTime<Utc>,
+    pub current: bool, // La sesión desde la que se hace la petición
+}
+
+// --- Exportación y borrado de datos (RGPD) ---
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct UserIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor_id: Option<i32>,
+    pub subject_id: Option<i32>,
+    pub action: String,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub status: Option<i16>,
+    pub token_id: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// GET /me/export: todo lo que guardamos del usuario
+#[derive(Debug, Serialize)]
+pub struct UserDataExport {
+    pub exported_at: DateTime<Utc>,
+    pub user: User,
+    pub roles: Vec<String>,
+    pub identities: Vec<UserIdentity>,
+    pub api_keys: Vec<ApiKey>,
+    pub passkeys: Vec<WebAuthnCredential>,
+    pub totp_enabled: bool,
+    pub sessions: Vec<SessionResponse>,
+    pub activities: Vec<RecentActivity>,
+    pub audit_log: Vec<AuditEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountDeletionResponse {
+    pub message: String,
+    pub purge_after: DateTime<Utc>, // Hasta entonces los datos siguen en BBDD, sin acceso
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
@@ -41,11 +446,16 @@ pub struct DashboardStat {
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct RecentActivity {
+    #[serde(default)] // Ausente en la caché del dashboard anterior a la paginación
+    pub id: i32,
     pub description: String,
+    pub created_at: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct SystemAlert {
+    #[serde(default)]
+    pub id: i32,
     pub message: String,
     pub severity: String,
 }
@@ -63,4 +473,6 @@ pub struct DashboardData {
 pub struct AppState {
     pub pool: PgPool,
     pub redis_client: redis::Client, // Cliente de Redis (es thread-safe y barato de clonar)
+    pub mailer: Arc<dyn Mailer>, // Envío de emails (intercambiable: fichero en local, SMTP...)
+    pub storage: Arc<dyn Storage>, // Ficheros subidos (intercambiable: disco en local, S3...)
 }
@@ -0,0 +1,88 @@
+// ============================================================================
+// CLIENTES OAUTH2 (grant client_credentials)
+// ============================================================================
+//
+// Servicios internos que necesitan tokens sin ser un usuario de `users`.
+// Cada cliente tiene un client_id público (svc_<hex>) y un secreto aleatorio
+// que solo se muestra al crearlo. En BBDD se guarda SHA-256(secreto): igual
+// que con las API keys, al ser de alta entropía no hace falta un hash lento.
+//
+// POST /oauth/token (application/x-www-form-urlencoded, RFC 6749 §4.4)
+//   grant_type=client_credentials&scope=dashboard:read
+//   Credenciales en Authorization: Basic base64(client_id:client_secret)
+//   o en el formulario (client_id, client_secret).
+//
+// ============================================================================
+
+use axum::http::{header, HeaderMap};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+pub struct GeneratedClient {
+    pub client_id: String,
+    pub secret: String,
+    pub secret_hash: String,
+}
+
+pub fn generate_client() -> GeneratedClient {
+    let mut rng = rand::thread_rng();
+
+    let mut id_bytes = [0u8; 8];
+    rng.fill_bytes(&mut id_bytes);
+    let client_id = format!("svc_{}", id_bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+
+    let mut secret_bytes = [0u8; 32];
+    rng.fill_bytes(&mut secret_bytes);
+    let secret = URL_SAFE_NO_PAD.encode(secret_bytes);
+    let secret_hash = hash_client_secret(&secret);
+
+    GeneratedClient { client_id, secret, secret_hash }
+}
+
+pub fn hash_client_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// Compara el secreto con el hash guardado en tiempo constante
+pub fn verify_client_secret(secret: &str, secret_hash: &str) -> bool {
+    let candidate = hash_client_secret(secret);
+    candidate.len() == secret_hash.len()
+        && candidate
+            .bytes()
+            .zip(secret_hash.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Credenciales de `Authorization: Basic` (client_secret_basic)
+pub fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (client_id, secret) = decoded.split_once(':')?;
+    Some((client_id.to_string(), secret.to_string()))
+}
+
+/// Scopes del token: los pedidos (separados por espacios) o, si no se piden,
+/// todos los del cliente. None si pide alguno que no tiene.
+pub fn resolve_scopes(requested: Option<&str>, allowed: &[String]) -> Option<Vec<String>> {
+    match requested.map(str::trim).filter(|s| !s.is_empty()) {
+        None => Some(allowed.to_vec()),
+        Some(requested) => {
+            let mut scopes: Vec<String> = Vec::new();
+            for scope in requested.split_whitespace() {
+                if !allowed.iter().any(|a| a == scope) {
+                    return None;
+                }
+                if !scopes.iter().any(|s| s == scope) {
+                    scopes.push(scope.to_string());
+                }
+            }
+            Some(scopes)
+        }
+    }
+}
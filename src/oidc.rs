@@ -0,0 +1,390 @@
+// ============================================================================
+// LOGIN CON OPENID CONNECT (Authorization Code + PKCE)
+// ============================================================================
+//
+// Flujo:
+//   1. GET /auth/oidc/login     -> guardamos state + nonce + code_verifier en
+//                                  Redis, atamos el state al navegador con una
+//                                  cookie HttpOnly (su hash) y redirigimos al IdP
+//   2. El usuario se autentica en el IdP, que vuelve a /auth/oidc/callback
+//      con ?code=...&state=...
+//   3. Comprobamos que el state es el de la cookie (si no, alguien intenta
+//      colar su login en otro navegador: login CSRF), lo consumimos (un solo
+//      uso), canjeamos el code por tokens en el token_endpoint enviando el
+//      code_verifier (PKCE) y validamos el ID token: firma (JWKS del IdP),
+//      iss, aud, exp y nonce.
+//   4. El handler provisiona al usuario (JIT), aplica nuestro MFA si lo tiene
+//      activo y emite NUESTROS tokens (JSON o cookies, según se pidió en el paso 1).
+//
+// Configuración (si OIDC_ISSUER_URL no está definida, el login OIDC se desactiva):
+//   OIDC_ISSUER_URL       Issuer; se lee {issuer}/.well-known/openid-configuration
+//   OIDC_CLIENT_ID        client_id registrado en el IdP
+//   OIDC_CLIENT_SECRET    Opcional (clientes confidenciales, client_secret_post)
+//   OIDC_REDIRECT_URL     Default: {APP_BASE_URL}/auth/oidc/callback
+//   OIDC_SCOPES           Default: "openid email profile"
+//
+// tests/oidc.rs prueba el flujo completo contra un IdP falso.
+//
+// ============================================================================
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use lazy_static::lazy_static;
+use rand::RngCore;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{env, time::{Duration, Instant}};
+use tokio::sync::RwLock;
+use crate::{auth, error::AppError, mailer};
+
+/// Tiempo que el usuario tiene para completar el login en el IdP
+pub const STATE_TTL_SECONDS: u64 = 600;
+
+/// Cada cuánto se vuelven a leer el discovery document y el JWKS
+const DISCOVERY_CACHE_SECONDS: u64 = 3600;
+
+pub struct OidcConfig {
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: String,
+}
+
+impl OidcConfig {
+    fn from_env() -> Option<Self> {
+        let issuer_url = env::var("OIDC_ISSUER_URL").ok()?.trim_end_matches('/').to_string();
+
+        let client_id = match env::var("OIDC_CLIENT_ID") {
+            Ok(client_id) => client_id,
+            Err(_) => {
+                tracing::warn!("OIDC_ISSUER_URL definida sin OIDC_CLIENT_ID: login OIDC desactivado");
+                return None;
+            }
+        };
+
+        Some(OidcConfig {
+            issuer_url,
+            client_id,
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok().filter(|s| !s.is_empty()),
+            redirect_url: env::var("OIDC_REDIRECT_URL")
+                .unwrap_or_else(|_| format!("{}/auth/oidc/callback", mailer::app_base_url())),
+            scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+        })
+    }
+}
+
+/// Campos del discovery document que usamos
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+struct Provider {
+    metadata: ProviderMetadata,
+    jwks: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Lo que guardamos en Redis entre /login y /callback
+#[derive(Serialize, Deserialize)]
+struct PendingLogin {
+    nonce: String,
+    code_verifier: String,
+    /// El navegador pidió sesión con cookies (la vuelta del IdP no trae cabeceras propias)
+    #[serde(default)]
+    cookie_session: bool,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Claims del ID token que necesitamos para identificar y provisionar al usuario
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+}
+
+lazy_static! {
+    static ref CONFIG: Option<OidcConfig> = OidcConfig::from_env();
+    static ref PROVIDER: RwLock<Option<Provider>> = RwLock::new(None);
+    static ref HTTP: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("No se pudo crear el cliente HTTP");
+}
+
+/// Configuración OIDC, o error 404 si el login OIDC no está habilitado
+pub fn config() -> Result<&'static OidcConfig, AppError> {
+    CONFIG
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("OIDC login is not configured".to_string()))
+}
+
+fn provider_error(context: &'static str) -> impl Fn(reqwest::Error) -> AppError {
+    move |e| {
+        tracing::error!("Error hablando con el IdP ({}): {}", context, e);
+        AppError::DatabaseError(sqlx::Error::Protocol("Identity provider error".into()))
+    }
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Lo que se guarda en la cookie del navegador: nunca el state en claro
+pub fn state_binding(state: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(state.as_bytes()))
+}
+
+/// code_challenge = BASE64URL(SHA256(code_verifier))  (RFC 7636, método S256)
+fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+// ----------------------------------------------------------------------------
+// DISCOVERY + JWKS
+// ----------------------------------------------------------------------------
+
+async fn fetch_provider(config: &OidcConfig) -> Result<Provider, AppError> {
+    let discovery_url = format!("{}/.well-known/openid-configuration", config.issuer_url);
+    let metadata: ProviderMetadata = HTTP
+        .get(&discovery_url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(provider_error("discovery"))?
+        .json()
+        .await
+        .map_err(provider_error("discovery"))?;
+
+    // OIDC Discovery §4.3: el issuer del documento debe ser exactamente el configurado
+    if metadata.issuer.trim_end_matches('/') != config.issuer_url {
+        tracing::error!("El issuer del discovery ({}) no coincide con OIDC_ISSUER_URL", metadata.issuer);
+        return Err(AppError::DatabaseError(sqlx::Error::Protocol("Identity provider error".into())));
+    }
+
+    let jwks: JwkSet = HTTP
+        .get(&metadata.jwks_uri)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(provider_error("jwks"))?
+        .json()
+        .await
+        .map_err(provider_error("jwks"))?;
+
+    tracing::info!("Discovery OIDC cargado de {} ({} claves)", config.issuer_url, jwks.keys.len());
+    Ok(Provider { metadata, jwks, fetched_at: Instant::now() })
+}
+
+/// Metadata del proveedor (cacheada DISCOVERY_CACHE_SECONDS)
+async fn provider_metadata(config: &OidcConfig, force_refresh: bool) -> Result<(ProviderMetadata, JwkSet), AppError> {
+    if !force_refresh {
+        let cached = PROVIDER.read().await;
+        if let Some(provider) = cached.as_ref() {
+            if provider.fetched_at.elapsed() < Duration::from_secs(DISCOVERY_CACHE_SECONDS) {
+                return Ok((provider.metadata.clone(), provider.jwks.clone()));
+            }
+        }
+    }
+
+    let provider = fetch_provider(config).await?;
+    let result = (provider.metadata.clone(), provider.jwks.clone());
+    *PROVIDER.write().await = Some(provider);
+    Ok(result)
+}
+
+// ----------------------------------------------------------------------------
+// PASO 1: URL DE AUTORIZACIÓN
+// ----------------------------------------------------------------------------
+
+/// `login_hint` (opcional) se reenvía al IdP para preseleccionar la cuenta.
+/// Devuelve (URL, state); el handler ata el state al navegador con una cookie.
+pub async fn authorization_url(
+    redis_client: &redis::Client,
+    login_hint: Option<&str>,
+    cookie_session: bool,
+) -> Result<(String, String), AppError> {
+    let config = config()?;
+    let (metadata, _) = provider_metadata(config, false).await?;
+
+    let state = random_token();
+    let pending = PendingLogin { nonce: random_token(), code_verifier: random_token(), cookie_session };
+
+    let mut conn = auth::redis_connection(redis_client).await?;
+    let _: () = conn
+        .set_ex(
+            format!("oidc_state:{}", state),
+            serde_json::to_string(&pending).unwrap_or_default(),
+            STATE_TTL_SECONDS,
+        )
+        .await
+        .map_err(auth::redis_command_error("SET"))?;
+
+    let mut url = url::Url::parse(&metadata.authorization_endpoint).map_err(|e| {
+        tracing::error!("authorization_endpoint inválido: {}", e);
+        AppError::DatabaseError(sqlx::Error::Protocol("Identity provider error".into()))
+    })?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_url)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &pending.nonce)
+        .append_pair("code_challenge", &pkce_challenge(&pending.code_verifier))
+        .append_pair("code_challenge_method", "S256");
+    if let Some(login_hint) = login_hint {
+        url.query_pairs_mut().append_pair("login_hint", login_hint);
+    }
+
+    Ok((url.into(), state))
+}
+
+// ----------------------------------------------------------------------------
+// PASO 2: CALLBACK (canje del code + validación del ID token)
+// ----------------------------------------------------------------------------
+
+/// `browser_binding` es el valor de la cookie que pusimos en el paso 1.
+/// Devuelve los claims y si el navegador pidió sesión con cookies.
+pub async fn complete_login(
+    redis_client: &redis::Client,
+    code: &str,
+    state: &str,
+    browser_binding: Option<&str>,
+) -> Result<(IdTokenClaims, bool), AppError> {
+    let config = config()?;
+
+    if browser_binding != Some(state_binding(state).as_str()) {
+        tracing::warn!("Callback OIDC con un state que no es de este navegador");
+        return Err(AppError::AuthError("OIDC state does not belong to this browser".to_string()));
+    }
+
+    // El state es de un solo uso: GETDEL evita que se reutilice un callback
+    let mut conn = auth::redis_connection(redis_client).await?;
+    let pending: Option<String> = conn
+        .get_del(format!("oidc_state:{}", state))
+        .await
+        .map_err(auth::redis_command_error("GETDEL"))?;
+    let pending: PendingLogin = pending
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .ok_or_else(|| AppError::AuthError("Invalid or expired OIDC state".to_string()))?;
+
+    let (metadata, _) = provider_metadata(config, false).await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", config.redirect_url.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("code_verifier", pending.code_verifier.as_str()),
+    ];
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+
+    let response = HTTP
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .map_err(provider_error("token"))?;
+
+    if !response.status().is_success() {
+        // Normalmente un code caducado o ya usado: es un fallo del login, no del servidor
+        tracing::warn!("El IdP rechazó el canje del code: {}", response.status());
+        return Err(AppError::AuthError("OIDC code exchange failed".to_string()));
+    }
+
+    let tokens: TokenResponse = response.json().await.map_err(provider_error("token"))?;
+    let claims = validate_id_token(config, &tokens.id_token, &pending.nonce).await?;
+    Ok((claims, pending.cookie_session))
+}
+
+async fn validate_id_token(config: &OidcConfig, id_token: &str, nonce: &str) -> Result<IdTokenClaims, AppError> {
+    let invalid = |reason: &str| {
+        tracing::warn!("ID token rechazado: {}", reason);
+        AppError::AuthError("Invalid ID token".to_string())
+    };
+
+    let header = jsonwebtoken::decode_header(id_token).map_err(|_| invalid("cabecera ilegible"))?;
+
+    // Solo firmas asimétricas: HS* usaría el client_secret como clave
+    if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+        return Err(invalid("algoritmo simétrico"));
+    }
+
+    let (metadata, mut jwks) = provider_metadata(config, false).await?;
+
+    // Kid desconocido: puede que el IdP haya rotado claves, releemos el JWKS una vez
+    let find_key = |jwks: &JwkSet| match &header.kid {
+        Some(kid) => jwks.find(kid).cloned(),
+        None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+        None => None,
+    };
+    let jwk = match find_key(&jwks) {
+        Some(jwk) => jwk,
+        None => {
+            jwks = provider_metadata(config, true).await?.1;
+            find_key(&jwks).ok_or_else(|| invalid("kid desconocido"))?
+        }
+    };
+
+    let key = DecodingKey::from_jwk(&jwk).map_err(|_| invalid("JWK inválida"))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    validation.leeway = 60;
+
+    let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+        .map_err(|e| invalid(&e.to_string()))?
+        .claims;
+
+    // El nonce liga el ID token a ESTE intento de login (evita replays)
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(invalid("nonce no coincide"));
+    }
+
+    Ok(claims)
+}
+
+// ----------------------------------------------------------------------------
+// PROVISIONING
+// ----------------------------------------------------------------------------
+
+/// Username local propuesto para una identidad nueva: preferred_username, o la
+/// parte local del email, limitado a caracteres seguros. El handler añade un
+/// sufijo si ya está cogido.
+pub fn username_candidate(claims: &IdTokenClaims) -> String {
+    let source = claims
+        .preferred_username
+        .as_deref()
+        .or_else(|| claims.email.as_deref().and_then(|email| email.split('@').next()))
+        .unwrap_or("user");
+
+    let username: String = source
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        .take(40)
+        .collect::<String>()
+        .to_lowercase();
+
+    if username.len() < 3 { format!("user_{}", username) } else { username }
+}
@@ -0,0 +1,200 @@
+// ============================================================================
+// PAGINACIÓN POR CURSOR (KEYSET)
+// ============================================================================
+//
+// En vez de OFFSET (que recorre y descarta todas las filas anteriores), cada
+// página continúa desde la última fila de la anterior:
+//
+//   WHERE (columna, id) > (valor_de_la_última, id_de_la_última)
+//   ORDER BY columna, id
+//   LIMIT limit + 1          -- la fila extra solo indica si hay más
+//
+// El `id` desempata filas con el mismo valor de ordenación. El cursor es
+// opaco para el cliente: base64url de {sort, desc, value, id}. Solo vale para
+// el orden con el que se generó.
+//
+// Cada listado declara sus campos de ordenación permitidos (lista blanca):
+// el nombre que ve el cliente nunca se interpola en el SQL, solo la columna
+// que nosotros asociamos a él.
+//
+// Uso desde db.rs (los parámetros del cursor son los dos últimos):
+//
+//   let sql = format!(
+//       "SELECT ... FROM t WHERE ($1::TEXT IS NULL OR filtro = $1) AND {} ORDER BY {} LIMIT {}",
+//       page.keyset_condition(2), page.order_by(), page.fetch_limit()
+//   );
+//   let rows = query_as(&sql).bind(filtro).bind(page.cursor_value()).bind(page.cursor_id())...
+//   Ok(page.build_page(rows, |row| match page.sort_name() { ... => (row.columna.to_string(), row.id) }))
+//
+// ============================================================================
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use crate::error::AppError;
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 100;
+
+/// Campo por el que un listado permite ordenar
+pub struct SortField {
+    /// Nombre en la query string (?sort=username, ?sort=-username)
+    pub name: &'static str,
+    /// Expresión SQL que se ordena y compara (debe devolver siempre un valor, sin NULLs)
+    pub column: &'static str,
+    /// Tipo SQL al que se convierte el valor del cursor
+    pub sql_type: &'static str,
+}
+
+impl SortField {
+    /// ¿Postgres aceptará `value` en el CAST al tipo de la columna? El cursor
+    /// viene del cliente: si no encaja, es un 400 y no un error de la base de datos
+    fn accepts(&self, value: &str) -> bool {
+        match self.sql_type {
+            "INTEGER" => value.parse::<i32>().is_ok(),
+            "BIGINT" => value.parse::<i64>().is_ok(),
+            "TIMESTAMP" => chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f").is_ok(),
+            // Postgres no admite el byte nulo en TEXT
+            _ => !value.contains('\0'),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    desc: bool,
+    value: String,
+    id: i32,
+}
+
+/// Respuesta paginada
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor para pedir la siguiente página (?after=...). None en la última.
+    pub next_cursor: Option<String>,
+}
+
+pub struct PageRequest {
+    limit: i64,
+    sort: &'static SortField,
+    descending: bool,
+    after: Option<Cursor>,
+}
+
+impl PageRequest {
+    /// Valida limit, sort (contra la lista blanca; el primero es el default) y cursor
+    pub fn parse(
+        limit: Option<i64>,
+        after: Option<&str>,
+        sort: Option<&str>,
+        fields: &'static [SortField],
+    ) -> Result<Self, AppError> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(AppError::BadRequest(format!("limit must be between 1 and {}", MAX_LIMIT)));
+        }
+
+        let (name, descending) = match sort {
+            Some(sort) => match sort.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (sort, false),
+            },
+            None => (fields[0].name, false),
+        };
+        let sort = fields.iter().find(|f| f.name == name).ok_or_else(|| {
+            let allowed: Vec<&str> = fields.iter().map(|f| f.name).collect();
+            AppError::BadRequest(format!("sort must be one of: {} (prefix with - for descending)", allowed.join(", ")))
+        })?;
+
+        let after = match after {
+            Some(after) => {
+                let cursor = decode_cursor(after).ok_or_else(|| AppError::BadRequest("Invalid cursor".to_string()))?;
+                if cursor.sort != sort.name || cursor.desc != descending {
+                    return Err(AppError::BadRequest("Cursor does not match the requested sort".to_string()));
+                }
+                if !sort.accepts(&cursor.value) {
+                    return Err(AppError::BadRequest("Invalid cursor".to_string()));
+                }
+                Some(cursor)
+            }
+            None => None,
+        };
+
+        Ok(PageRequest { limit, sort, descending, after })
+    }
+
+    /// Nombre del campo de ordenación elegido (para sacar el valor del cursor de cada fila)
+    pub fn sort_name(&self) -> &'static str {
+        self.sort.name
+    }
+
+    /// Condición keyset. `first_param` es el índice del parámetro con el valor
+    /// del cursor; el siguiente es su id. Sin cursor, ambos se enlazan a NULL.
+    pub fn keyset_condition(&self, first_param: usize) -> String {
+        let operator = if self.descending { "<" } else { ">" };
+        format!(
+            "(${value}::TEXT IS NULL OR ({column}, id) {op} (CAST(${value} AS {ty}), ${id}))",
+            value = first_param,
+            id = first_param + 1,
+            column = self.sort.column,
+            op = operator,
+            ty = self.sort.sql_type,
+        )
+    }
+
+    pub fn order_by(&self) -> String {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        format!("{} {dir}, id {dir}", self.sort.column, dir = direction)
+    }
+
+    /// Una fila más de las pedidas para saber si hay página siguiente
+    pub fn fetch_limit(&self) -> i64 {
+        self.limit + 1
+    }
+
+    pub fn cursor_value(&self) -> Option<String> {
+        self.after.as_ref().map(|c| c.value.clone())
+    }
+
+    pub fn cursor_id(&self) -> Option<i32> {
+        self.after.as_ref().map(|c| c.id)
+    }
+
+    /// Recorta la fila extra y genera el cursor desde la última fila devuelta.
+    /// `key` da el valor de la columna de ordenación (como texto) y el id de una fila.
+    pub fn build_page<T>(&self, mut rows: Vec<T>, key: impl Fn(&T) -> (String, i32)) -> Page<T> {
+        let has_more = rows.len() as i64 > self.limit;
+        rows.truncate(self.limit as usize);
+
+        let next_cursor = if has_more {
+            rows.last().map(|last| {
+                let (value, id) = key(last);
+                encode_cursor(&Cursor {
+                    sort: self.sort.name.to_string(),
+                    desc: self.descending,
+                    value,
+                    id,
+                })
+            })
+        } else {
+            None
+        };
+
+        Page { items: rows, next_cursor }
+    }
+}
+
+fn encode_cursor(cursor: &Cursor) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(cursor).unwrap_or_default())
+}
+
+fn decode_cursor(cursor: &str) -> Option<Cursor> {
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()
+}
+
+/// Patrón LIKE "empieza por", escapando los comodines que traiga el usuario
+pub fn like_prefix(prefix: &str) -> String {
+    let escaped = prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("{}%", escaped)
+}
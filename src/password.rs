@@ -0,0 +1,230 @@
+// ============================================================================
+// HASHING DE CONTRASEÑAS (intercambiable)
+// ============================================================================
+//
+// `PasswordHasher` abstrae el algoritmo. Hay uno ACTUAL (con el que se hashea
+// todo lo nuevo) y los demás solo se usan para VERIFICAR hashes antiguos:
+//
+//   PASSWORD_HASH_ALGORITHM=argon2id (default) | bcrypt
+//
+// Tras un login correcto, si el hash guardado es de otro algoritmo o de
+// parámetros más débiles que los configurados, se rehashea con la contraseña
+// en claro que acabamos de recibir. Así la tabla `users` migra sola.
+//
+// Mientras convivan algoritmos, cada uno tarda distinto: si un usuario
+// inexistente costara lo mismo que uno con hash Argon2id pero no lo mismo que
+// uno con bcrypt, el tiempo de respuesta del login delataría qué cuentas
+// existen. Por eso `verify_login` verifica SIEMPRE una vez con cada algoritmo
+// registrado: con el hash real el que lo reconoce y con un hash señuelo propio
+// el resto.
+//
+// ============================================================================
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use lazy_static::lazy_static;
+use std::env;
+use uuid::Uuid;
+use crate::error::AppError;
+
+pub trait PasswordHasher: Send + Sync {
+    /// ¿Este hash lo ha generado este algoritmo?
+    fn recognizes(&self, hash: &str) -> bool;
+    fn hash(&self, password: &str) -> Result<String, AppError>;
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, AppError>;
+    /// ¿El hash usa parámetros distintos de los configurados ahora?
+    fn is_outdated(&self, hash: &str) -> bool;
+}
+
+fn hashing_error(context: &str, e: impl std::fmt::Display) -> AppError {
+    tracing::error!("{}: {}", context, e);
+    AppError::DatabaseError(sqlx::Error::Protocol(context.to_string()))
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+// ----------------------------------------------------------------------------
+// Argon2id
+// ----------------------------------------------------------------------------
+
+pub struct Argon2idHasher {
+    params: Params,
+}
+
+impl Argon2idHasher {
+    /// Defaults recomendados por OWASP: 19 MiB, 2 iteraciones, 1 hilo
+    pub fn from_env() -> Self {
+        let params = Params::new(
+            env_or("ARGON2_MEMORY_KIB", 19_456),
+            env_or("ARGON2_ITERATIONS", 2),
+            env_or("ARGON2_PARALLELISM", 1),
+            None,
+        )
+        .expect("Parámetros de Argon2 inválidos");
+
+        Argon2idHasher { params }
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+impl PasswordHasher for Argon2idHasher {
+    fn recognizes(&self, hash: &str) -> bool {
+        hash.starts_with("$argon2")
+    }
+
+    fn hash(&self, password: &str) -> Result<String, AppError> {
+        let salt = SaltString::generate(&mut OsRng);
+        self.argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| hashing_error("Error hashing password", e))
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, AppError> {
+        // Los parámetros de verificación salen del propio hash, no de la config
+        let parsed = PasswordHash::new(hash).map_err(|e| hashing_error("Error verifying password", e))?;
+        Ok(Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+    }
+
+    fn is_outdated(&self, hash: &str) -> bool {
+        let Ok(parsed) = PasswordHash::new(hash) else {
+            return true;
+        };
+        let Ok(params) = Params::try_from(&parsed) else {
+            return true;
+        };
+
+        parsed.algorithm.as_str() != "argon2id"
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+    }
+}
+
+// ----------------------------------------------------------------------------
+// bcrypt (hashes existentes en users.password_hash)
+// ----------------------------------------------------------------------------
+
+pub struct BcryptHasher {
+    cost: u32,
+}
+
+impl BcryptHasher {
+    pub fn from_env() -> Self {
+        BcryptHasher {
+            cost: env_or("BCRYPT_COST", bcrypt::DEFAULT_COST),
+        }
+    }
+}
+
+impl PasswordHasher for BcryptHasher {
+    fn recognizes(&self, hash: &str) -> bool {
+        ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+    }
+
+    fn hash(&self, password: &str) -> Result<String, AppError> {
+        bcrypt::hash(password, self.cost).map_err(|e| hashing_error("Error hashing password", e))
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, AppError> {
+        bcrypt::verify(password, hash).map_err(|e| hashing_error("Error verifying password", e))
+    }
+
+    fn is_outdated(&self, hash: &str) -> bool {
+        // Formato: $2b$<coste>$<salt+hash>
+        hash.split('$').nth(2).and_then(|cost| cost.parse::<u32>().ok()) != Some(self.cost)
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Registro: algoritmo actual + algoritmos heredados
+// ----------------------------------------------------------------------------
+
+pub struct PasswordHashers {
+    current: Box<dyn PasswordHasher>,
+    legacy: Vec<Box<dyn PasswordHasher>>,
+    /// Un hash señuelo por algoritmo, en el mismo orden que `all()`
+    dummies: Vec<String>,
+}
+
+lazy_static! {
+    static ref HASHERS: PasswordHashers = PasswordHashers::from_env();
+}
+
+pub fn hashers() -> &'static PasswordHashers {
+    &HASHERS
+}
+
+impl PasswordHashers {
+    fn from_env() -> Self {
+        let argon2id: Box<dyn PasswordHasher> = Box::new(Argon2idHasher::from_env());
+        let bcrypt: Box<dyn PasswordHasher> = Box::new(BcryptHasher::from_env());
+
+        let (current, legacy) =
+            match env::var("PASSWORD_HASH_ALGORITHM").unwrap_or_else(|_| "argon2id".to_string()).as_str() {
+                "bcrypt" => (bcrypt, vec![argon2id]),
+                "argon2id" => (argon2id, vec![bcrypt]),
+                other => panic!("PASSWORD_HASH_ALGORITHM no soportado: {}", other),
+            };
+
+        let mut hashers = PasswordHashers { current, legacy, dummies: Vec::new() };
+        hashers.dummies = hashers
+            .all()
+            .map(|hasher| hasher.hash(&Uuid::new_v4().to_string()).expect("hash señuelo"))
+            .collect();
+        hashers
+    }
+
+    fn all(&self) -> impl Iterator<Item = &dyn PasswordHasher> {
+        std::iter::once(&self.current).chain(self.legacy.iter()).map(|hasher| hasher.as_ref())
+    }
+
+    fn find(&self, hash: &str) -> Option<&dyn PasswordHasher> {
+        self.all().find(|hasher| hasher.recognizes(hash))
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, AppError> {
+        self.current.hash(password)
+    }
+
+    /// Un hash en formato desconocido (p. ej. el placeholder de la migración) nunca verifica
+    pub fn verify(&self, password: &str, hash: &str) -> Result<bool, AppError> {
+        match self.find(hash) {
+            Some(hasher) => hasher.verify(password, hash),
+            None => {
+                tracing::warn!("Hash de contraseña en formato desconocido");
+                Ok(false)
+            }
+        }
+    }
+
+    /// Verificación del login con coste constante. `hash` es `None` si el
+    /// usuario no existe; un hash en formato desconocido tampoco verifica
+    pub fn verify_login(&self, password: &str, hash: Option<&str>) -> Result<bool, AppError> {
+        if hash.is_some_and(|hash| self.find(hash).is_none()) {
+            tracing::warn!("Hash de contraseña en formato desconocido");
+        }
+
+        let mut authenticated = false;
+        for (hasher, dummy) in self.all().zip(self.dummies.iter()) {
+            match hash.filter(|hash| hasher.recognizes(hash)) {
+                Some(hash) => authenticated = hasher.verify(password, hash)?,
+                None => {
+                    let _ = hasher.verify(password, dummy);
+                }
+            }
+        }
+        Ok(authenticated)
+    }
+
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        !self.current.recognizes(hash) || self.current.is_outdated(hash)
+    }
+}
@@ -0,0 +1,155 @@
+// ============================================================================
+// REVOCACIÓN DE ACCESS TOKENS (denylist de jti)
+// ============================================================================
+//
+// Un JWT es válido hasta su `exp` aunque el usuario haga logout. Para poder
+// invalidarlo antes, cada token lleva un `jti` único y mantenemos en Redis:
+//
+//   revoked_jti:<jti>                 -> "1"        (TTL = vida restante del token)
+//   tokens_revoked_before:<username>  -> timestamp  (todo token con iat anterior
+//                                                    queda revocado; TTL = vida máx.)
+//
+// El corte es estricto (iat < corte) para que un login hecho en el mismo segundo
+// que la revocación (p. ej. justo tras un reset de contraseña) siga siendo válido.
+//
+// Consultar Redis en CADA request sería caro, así que delante hay una caché
+// LRU en memoria del proceso:
+//   - "revocado" se cachea hasta que el token expira (nunca vuelve a ser válido)
+//   - "no revocado" se cachea solo unos segundos (DENYLIST_CACHE_TTL_SECONDS):
+//     una revocación hecha en OTRA réplica tarda como mucho eso en aplicarse.
+//
+// ============================================================================
+
+use lazy_static::lazy_static;
+use lru::LruCache;
+use redis::AsyncCommands;
+use std::{
+    env,
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use crate::{
+    auth::{self, redis_command_error, redis_connection},
+    error::AppError,
+    models::Claims,
+};
+
+const LOCAL_CACHE_CAPACITY: usize = 10_000;
+
+/// LRU con caducidad por entrada
+struct LocalCache<V> {
+    entries: LruCache<String, (V, Instant)>,
+}
+
+impl<V: Clone> LocalCache<V> {
+    fn new() -> Self {
+        LocalCache {
+            entries: LruCache::new(NonZeroUsize::new(LOCAL_CACHE_CAPACITY).unwrap()),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<V> {
+        match self.entries.get(key) {
+            Some((value, valid_until)) if *valid_until > Instant::now() => Some(value.clone()),
+            Some(_) => {
+                self.entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put(&mut self, key: String, value: V, ttl: Duration) {
+        self.entries.put(key, (value, Instant::now() + ttl));
+    }
+}
+
+lazy_static! {
+    /// jti -> revocado
+    static ref JTI_CACHE: Mutex<LocalCache<bool>> = Mutex::new(LocalCache::new());
+    /// username -> corte "revocados antes de" (None = sin corte)
+    static ref USER_CACHE: Mutex<LocalCache<Option<i64>>> = Mutex::new(LocalCache::new());
+}
+
+fn negative_cache_ttl() -> Duration {
+    let seconds = env::var("DENYLIST_CACHE_TTL_SECONDS")
+        .unwrap_or_else(|_| "5".to_string())
+        .parse()
+        .unwrap_or(5);
+    Duration::from_secs(seconds)
+}
+
+fn seconds_until(exp: usize) -> u64 {
+    (exp as i64 - chrono::Utc::now().timestamp()).max(1) as u64
+}
+
+/// Añade un jti a la denylist hasta que el token habría expirado por sí solo
+pub async fn revoke_jti(redis_client: &redis::Client, jti: &str, exp: usize) -> Result<(), AppError> {
+    let ttl = seconds_until(exp);
+    let mut conn = redis_connection(redis_client).await?;
+
+    let _: () = conn
+        .set_ex(format!("revoked_jti:{}", jti), 1, ttl)
+        .await
+        .map_err(redis_command_error("SET"))?;
+
+    JTI_CACHE.lock().unwrap().put(jti.to_string(), true, Duration::from_secs(ttl));
+    tracing::info!("Access token {} revocado", jti);
+    Ok(())
+}
+
+/// Revoca todos los access tokens del usuario emitidos hasta ahora
+pub async fn revoke_tokens_issued_before_now(
+    redis_client: &redis::Client,
+    username: &str,
+) -> Result<(), AppError> {
+    let now = chrono::Utc::now().timestamp();
+    let ttl = auth::access_token_lifetime_seconds() as u64;
+    let mut conn = redis_connection(redis_client).await?;
+
+    let _: () = conn
+        .set_ex(format!("tokens_revoked_before:{}", username), now, ttl)
+        .await
+        .map_err(redis_command_error("SET"))?;
+
+    USER_CACHE.lock().unwrap().put(username.to_string(), Some(now), Duration::from_secs(ttl));
+    tracing::info!("Access tokens de {} revocados", username);
+    Ok(())
+}
+
+/// ¿Está revocado este token (por su jti o por un corte a nivel de usuario)?
+pub async fn is_revoked(redis_client: &redis::Client, claims: &Claims) -> Result<bool, AppError> {
+    let jti_cached = JTI_CACHE.lock().unwrap().get(&claims.jti);
+    let user_cached = USER_CACHE.lock().unwrap().get(&claims.sub);
+
+    let revoked_by_cutoff = |cutoff: Option<i64>| cutoff.is_some_and(|before| (claims.iat as i64) < before);
+
+    if jti_cached == Some(true) || user_cached.is_some_and(revoked_by_cutoff) {
+        return Ok(true);
+    }
+    if jti_cached.is_some() && user_cached.is_some() {
+        return Ok(false);
+    }
+
+    // Fallo de caché: una sola ida y vuelta a Redis para ambas claves
+    let mut conn = redis_connection(redis_client).await?;
+    let (jti_revoked, cutoff): (Option<String>, Option<i64>) = redis::pipe()
+        .get(format!("revoked_jti:{}", claims.jti))
+        .get(format!("tokens_revoked_before:{}", claims.sub))
+        .query_async(&mut conn)
+        .await
+        .map_err(redis_command_error("GET"))?;
+
+    let jti_revoked = jti_revoked.is_some();
+    let token_ttl = Duration::from_secs(seconds_until(claims.exp));
+    let short_ttl = negative_cache_ttl().min(token_ttl);
+
+    JTI_CACHE
+        .lock()
+        .unwrap()
+        .put(claims.jti.clone(), jti_revoked, if jti_revoked { token_ttl } else { short_ttl });
+    USER_CACHE.lock().unwrap().put(claims.sub.clone(), cutoff, short_ttl);
+
+    Ok(jti_revoked || revoked_by_cutoff(cutoff))
+}
@@ -0,0 +1,134 @@
+// ============================================================================
+// STORAGE (ficheros subidos, intercambiable)
+// ============================================================================
+//
+// Los handlers solo conocen el trait `Storage`: guardan bytes bajo una clave
+// ("avatars/7/<uuid>/256.png") y obtienen la URL pública para el cliente.
+//
+// En local usamos `LocalStorage`, que escribe en STORAGE_LOCAL_DIR y sirve
+// los ficheros desde la propia API en GET /media/<clave>. Un backend
+// S3-compatible solo tendría que implementar el trait (su public_url
+// apuntaría al bucket o al CDN) y elegirse en `from_env()`.
+//
+// Configuración:
+//   STORAGE_BACKEND      Default: local
+//   STORAGE_LOCAL_DIR    Default: uploads
+//   STORAGE_PUBLIC_URL   Default: APP_BASE_URL + /media
+//
+// ============================================================================
+
+use axum::async_trait;
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+use crate::{error::AppError, mailer};
+
+pub struct StoredObject {
+    pub bytes: Vec<u8>,
+    pub content_type: &'static str,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), AppError>;
+    async fn get(&self, key: &str) -> Result<Option<StoredObject>, AppError>;
+    /// Borra todos los objetos bajo el prefijo (ej. todas las miniaturas de un avatar)
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), AppError>;
+    fn public_url(&self, key: &str) -> String;
+}
+
+fn storage_error(e: std::io::Error) -> AppError {
+    tracing::error!("Storage error: {}", e);
+    AppError::DatabaseError(sqlx::Error::Protocol("Storage error".into()))
+}
+
+/// Las claves son rutas relativas sin "..": nunca se sale del directorio raíz
+fn is_safe_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '-' | '_' | '.'))
+        && Path::new(key).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+fn content_type_for(key: &str) -> &'static str {
+    match Path::new(key).extension().and_then(|e| e.to_str()) {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Guarda los objetos como ficheros bajo `root`
+pub struct LocalStorage {
+    root: PathBuf,
+    public_base_url: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>, public_base_url: impl Into<String>) -> Self {
+        LocalStorage {
+            root: root.into(),
+            public_base_url: public_base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn path_for(&self, key: &str) -> Option<PathBuf> {
+        is_safe_key(key).then(|| self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), AppError> {
+        let path = self
+            .path_for(key)
+            .ok_or_else(|| AppError::BadRequest("Invalid storage key".to_string()))?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(storage_error)?;
+        }
+        tokio::fs::write(&path, bytes).await.map_err(storage_error)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<StoredObject>, AppError> {
+        let Some(path) = self.path_for(key) else {
+            return Ok(None);
+        };
+
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(Some(StoredObject { bytes, content_type: content_type_for(key) })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), AppError> {
+        let Some(path) = self.path_for(prefix) else {
+            return Ok(());
+        };
+
+        match tokio::fs::remove_dir_all(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_base_url, key)
+    }
+}
+
+/// Elige la implementación según STORAGE_BACKEND (por ahora solo "local")
+pub fn from_env() -> Arc<dyn Storage> {
+    match env::var("STORAGE_BACKEND").unwrap_or_else(|_| "local".to_string()).as_str() {
+        "local" => {}
+        other => tracing::warn!("STORAGE_BACKEND '{}' no soportado, usando 'local'", other),
+    }
+
+    let root = env::var("STORAGE_LOCAL_DIR").unwrap_or_else(|_| "uploads".to_string());
+    let public_url = env::var("STORAGE_PUBLIC_URL").unwrap_or_else(|_| format!("{}/media", mailer::app_base_url()));
+    Arc::new(LocalStorage::new(root, public_url))
+}
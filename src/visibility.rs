@@ -0,0 +1,97 @@
+// ============================================================================
+// VISIBILIDAD DE CAMPOS (PII)
+// ============================================================================
+//
+// Un modelo declara qué campos son privados (email, metadatos...) y de quién
+// es cada fila. Al responder, se envuelve en `Masked` según quién pregunta:
+//
+//   - El dueño de la fila y quien tenga `users:read` (admins) lo ven todo.
+//   - El resto recibe el JSON sin los campos privados.
+//
+// Funciona con cualquier modelo que derive `Serialize`: basta con implementar
+// `FieldPolicy`. Ejemplo:
+//
+//   impl FieldPolicy for User {
+//       const PRIVATE_FIELDS: &'static [&'static str] = &["email", "email_verified_at"];
+//       fn owner_id(&self) -> Option<i32> { Some(self.id) }
+//   }
+//
+//   let viewer = Viewer::from(&auth_user);
+//   Json(viewer.mask(user))              // Masked<User>
+//   Json(viewer.mask_page(page))         // Page<Masked<User>>
+//
+// Los campos se quitan después de serializar: un campo nuevo del modelo es
+// público hasta que se añada a PRIVATE_FIELDS.
+//
+// ============================================================================
+
+use serde::{ser::Error as _, Serialize, Serializer};
+use crate::{extractors::AuthUser, pagination::Page};
+
+/// Permiso que da acceso a los campos privados de cualquier usuario
+pub const VIEW_PRIVATE_PERMISSION: &str = "users:read";
+
+pub trait FieldPolicy {
+    /// Campos que solo ven el dueño y los admins
+    const PRIVATE_FIELDS: &'static [&'static str];
+
+    /// Usuario dueño de la fila (None = nadie la ve completa salvo los admins)
+    fn owner_id(&self) -> Option<i32> {
+        None
+    }
+}
+
+/// Quién hace la petición, a efectos de visibilidad
+#[derive(Debug, Clone, Copy)]
+pub struct Viewer {
+    user_id: i32,
+    privileged: bool,
+}
+
+impl From<&AuthUser> for Viewer {
+    fn from(user: &AuthUser) -> Self {
+        Viewer { user_id: user.id, privileged: user.has_permission(VIEW_PRIVATE_PERMISSION) }
+    }
+}
+
+impl Viewer {
+    /// Ve los campos privados de todos (no solo los suyos)
+    pub fn is_privileged(&self) -> bool {
+        self.privileged
+    }
+
+    pub fn mask<T: FieldPolicy>(&self, value: T) -> Masked<T> {
+        let show_private = self.privileged || value.owner_id() == Some(self.user_id);
+        Masked { value, show_private }
+    }
+
+    pub fn mask_page<T: FieldPolicy>(&self, page: Page<T>) -> Page<Masked<T>> {
+        Page {
+            items: page.items.into_iter().map(|item| self.mask(item)).collect(),
+            next_cursor: page.next_cursor,
+        }
+    }
+}
+
+/// Modelo listo para serializar con los campos que el lector puede ver
+#[derive(Debug)]
+pub struct Masked<T> {
+    value: T,
+    show_private: bool,
+}
+
+impl<T: Serialize + FieldPolicy> Serialize for Masked<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.show_private {
+            return self.value.serialize(serializer);
+        }
+
+        let mut json = serde_json::to_value(&self.value).map_err(S::Error::custom)?;
+        if let serde_json::Value::Object(fields) = &mut json {
+            for field in T::PRIVATE_FIELDS {
+                fields.remove(*field);
+            }
+        }
+        json.serialize(serializer)
+    }
+}
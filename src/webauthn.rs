@@ -0,0 +1,591 @@
+// ============================================================================
+// PASSKEYS (WebAuthn)
+// ============================================================================
+//
+// Registro (usuario autenticado):
+//   1. POST /webauthn/register/start  -> opciones para navigator.credentials.create()
+//   2. POST /webauthn/register/finish -> attestationObject + clientDataJSON
+//      Guardamos la clave pública (COSE), el contador de firmas y los transports.
+//
+// Login (sin contraseña):
+//   1. POST /webauthn/login/start     -> opciones para navigator.credentials.get()
+//   2. POST /webauthn/login/finish    -> authenticatorData + firma
+//      Verificamos la firma sobre authenticatorData || SHA-256(clientDataJSON)
+//      con la clave guardada y emitimos los mismos tokens que /login.
+//
+// Solo se soporta attestation "none": nos basta con la clave pública, no
+// verificamos el fabricante del autenticador. Algoritmos: ES256 y RS256.
+// Todos los binarios viajan en base64url sin padding (como PublicKeyCredential.toJSON()).
+//
+// Configuración:
+//   WEBAUTHN_RP_ID      Dominio del Relying Party (default: host de APP_BASE_URL)
+//   WEBAUTHN_RP_NAME    Nombre que muestra el navegador (default: RustAPI)
+//   WEBAUTHN_ORIGINS    Orígenes aceptados, separados por comas (default: APP_BASE_URL)
+//
+// tests/webauthn.rs hace el registro y el login completos contra la API con
+// un autenticador por software.
+//
+// ============================================================================
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use lazy_static::lazy_static;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use rand::RngCore;
+use redis::AsyncCommands;
+use rsa::{pkcs1v15, BigUint, RsaPublicKey};
+use ciborium::Value;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::env;
+use crate::{
+    auth,
+    error::AppError,
+    mailer,
+    models::{AssertionCredential, RegistrationCredential, User, WebAuthnCredential},
+};
+
+/// Tiempo para completar la ceremonia en el navegador
+const CHALLENGE_TTL_SECONDS: u64 = 300;
+
+/// Identificadores COSE de los algoritmos soportados
+pub const COSE_ALG_ES256: i64 = -7;
+pub const COSE_ALG_RS256: i64 = -257;
+
+// Flags de authenticatorData (WebAuthn §6.1)
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// Longitud máxima de un credentialId (WebAuthn §6.5.1)
+const MAX_CREDENTIAL_ID_LENGTH: usize = 1023;
+
+struct WebAuthnConfig {
+    rp_id: String,
+    rp_name: String,
+    origins: Vec<String>,
+}
+
+lazy_static! {
+    static ref CONFIG: WebAuthnConfig = {
+        let base_url = mailer::app_base_url();
+        let default_rp_id = url::Url::parse(&base_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_else(|| "localhost".to_string());
+
+        WebAuthnConfig {
+            rp_id: env::var("WEBAUTHN_RP_ID").unwrap_or(default_rp_id),
+            rp_name: env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "RustAPI".to_string()),
+            origins: env::var("WEBAUTHN_ORIGINS")
+                .unwrap_or(base_url)
+                .split(',')
+                .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect(),
+        }
+    };
+}
+
+/// Lo que necesitamos guardar de una credencial recién registrada
+pub struct NewCredential {
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub algorithm: i32,
+    pub sign_count: i64,
+    pub transports: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Reto de login guardado en Redis (el usuario solo se conoce si se indicó username)
+#[derive(Serialize, Deserialize)]
+struct PendingAssertion {
+    user_id: Option<i32>,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    /// Solo en el registro: credential id + clave pública COSE
+    attested: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+fn invalid(reason: &str) -> AppError {
+    tracing::warn!("Respuesta WebAuthn rechazada: {}", reason);
+    AppError::AuthError("Invalid WebAuthn response".to_string())
+}
+
+fn decode_b64(value: &str, field: &str) -> Result<Vec<u8>, AppError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| invalid(&format!("{} no es base64url", field)))
+}
+
+fn new_challenge() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// user.id de WebAuthn: opaco y estable (el id interno, nunca el email)
+fn user_handle(user_id: i32) -> String {
+    URL_SAFE_NO_PAD.encode(user_id.to_be_bytes())
+}
+
+fn credential_descriptors(credentials: &[WebAuthnCredential]) -> Vec<serde_json::Value> {
+    credentials
+        .iter()
+        .map(|c| json!({ "type": "public-key", "id": c.credential_id, "transports": c.transports }))
+        .collect()
+}
+
+// ----------------------------------------------------------------------------
+// clientDataJSON y authenticatorData
+// ----------------------------------------------------------------------------
+
+fn parse_client_data(client_data_json: &[u8], expected_type: &str) -> Result<ClientData, AppError> {
+    let client_data: ClientData =
+        serde_json::from_slice(client_data_json).map_err(|_| invalid("clientDataJSON ilegible"))?;
+
+    if client_data.ceremony != expected_type {
+        return Err(invalid("tipo de ceremonia incorrecto"));
+    }
+    if !CONFIG.origins.contains(&client_data.origin) {
+        return Err(invalid(&format!("origen no permitido: {}", client_data.origin)));
+    }
+    Ok(client_data)
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData<'_>, AppError> {
+    // rpIdHash (32) | flags (1) | signCount (4) | [attestedCredentialData] | [extensions]
+    if data.len() < 37 {
+        return Err(invalid("authenticatorData demasiado corto"));
+    }
+
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let attested = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // aaguid (16) | credentialIdLength (2) | credentialId | credentialPublicKey (CBOR)
+        let rest = &data[37..];
+        if rest.len() < 18 {
+            return Err(invalid("attestedCredentialData incompleto"));
+        }
+        let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        if id_len > MAX_CREDENTIAL_ID_LENGTH {
+            return Err(invalid("credentialId demasiado largo"));
+        }
+        let credential_id = rest.get(18..18 + id_len).ok_or_else(|| invalid("credentialId incompleto"))?;
+
+        // La clave COSE va seguida de las extensiones: hay que saber dónde acaba.
+        // El lector avanza sobre el slice, lo que queda son las extensiones
+        let key_bytes = &rest[18 + id_len..];
+        let mut reader = key_bytes;
+        ciborium::de::from_reader::<Value, _>(&mut reader).map_err(|_| invalid("clave COSE ilegible"))?;
+        let key_len = key_bytes.len() - reader.len();
+
+        Some((credential_id.to_vec(), key_bytes[..key_len].to_vec()))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData { rp_id_hash: &data[..32], flags, sign_count, attested })
+}
+
+/// rpIdHash correcto y el usuario presente y verificado (PIN, biometría...)
+fn check_authenticator_flags(auth_data: &AuthenticatorData) -> Result<(), AppError> {
+    if auth_data.rp_id_hash != Sha256::digest(CONFIG.rp_id.as_bytes()).as_slice() {
+        return Err(invalid("rpIdHash no coincide"));
+    }
+    if auth_data.flags & FLAG_USER_PRESENT == 0 {
+        return Err(invalid("usuario no presente"));
+    }
+    if auth_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(invalid("usuario no verificado"));
+    }
+    Ok(())
+}
+
+// ----------------------------------------------------------------------------
+// Claves COSE y firmas
+// ----------------------------------------------------------------------------
+
+/// Una clave COSE es un mapa CBOR con claves enteras
+fn cose_map(cose_key: &[u8]) -> Result<Vec<(Value, Value)>, AppError> {
+    ciborium::de::from_reader::<Value, _>(cose_key)
+        .ok()
+        .and_then(|value| value.into_map().ok())
+        .ok_or_else(|| invalid("clave COSE ilegible"))
+}
+
+fn cose_get(map: &[(Value, Value)], key: i128) -> Option<&Value> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().map(i128::from) == Some(key))
+        .map(|(_, value)| value)
+}
+
+fn cose_int(map: &[(Value, Value)], key: i128) -> Option<i128> {
+    cose_get(map, key)?.as_integer().map(i128::from)
+}
+
+fn cose_bytes(map: &[(Value, Value)], key: i128) -> Option<&[u8]> {
+    cose_get(map, key)?.as_bytes().map(Vec::as_slice)
+}
+
+/// Algoritmo de una clave COSE (solo si es uno que sabemos verificar)
+fn cose_algorithm(cose_key: &[u8]) -> Result<i64, AppError> {
+    let map = cose_map(cose_key)?;
+
+    // kty: 2 = EC2, 3 = RSA
+    match (cose_int(&map, 1), cose_int(&map, 3)) {
+        (Some(2), Some(alg)) if alg == COSE_ALG_ES256 as i128 && cose_int(&map, -1) == Some(1) => Ok(COSE_ALG_ES256),
+        (Some(3), Some(alg)) if alg == COSE_ALG_RS256 as i128 => Ok(COSE_ALG_RS256),
+        _ => Err(invalid("algoritmo de clave no soportado")),
+    }
+}
+
+fn verify_signature(cose_key: &[u8], algorithm: i64, message: &[u8], signature: &[u8]) -> Result<(), AppError> {
+    let map = cose_map(cose_key)?;
+
+    let valid = match algorithm {
+        COSE_ALG_ES256 => {
+            // Clave pública sin comprimir: 0x04 | x | y
+            let (x, y) = cose_bytes(&map, -2).zip(cose_bytes(&map, -3)).ok_or_else(|| invalid("clave EC2 incompleta"))?;
+            let mut point = vec![0x04];
+            point.extend_from_slice(x);
+            point.extend_from_slice(y);
+
+            let key = VerifyingKey::from_sec1_bytes(&point).map_err(|_| invalid("clave EC2 inválida"))?;
+            let signature = Signature::from_der(signature).map_err(|_| invalid("firma ECDSA mal formada"))?;
+            key.verify(message, &signature).is_ok()
+        }
+        COSE_ALG_RS256 => {
+            let (n, e) = cose_bytes(&map, -1).zip(cose_bytes(&map, -2)).ok_or_else(|| invalid("clave RSA incompleta"))?;
+            let key = RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e))
+                .map_err(|_| invalid("clave RSA inválida"))?;
+            let signature = pkcs1v15::Signature::try_from(signature).map_err(|_| invalid("firma RSA mal formada"))?;
+            rsa::signature::Verifier::verify(&pkcs1v15::VerifyingKey::<Sha256>::new(key), message, &signature).is_ok()
+        }
+        _ => false,
+    };
+
+    if valid { Ok(()) } else { Err(invalid("firma incorrecta")) }
+}
+
+// ----------------------------------------------------------------------------
+// REGISTRO
+// ----------------------------------------------------------------------------
+
+/// Opciones para navigator.credentials.create(). Un reto pendiente por usuario.
+pub async fn start_registration(
+    redis_client: &redis::Client,
+    user: &User,
+    existing: &[WebAuthnCredential],
+) -> Result<serde_json::Value, AppError> {
+    let challenge = new_challenge();
+
+    let mut conn = auth::redis_connection(redis_client).await?;
+    let _: () = conn
+        .set_ex(format!("webauthn_register:{}", user.id), &challenge, CHALLENGE_TTL_SECONDS)
+        .await
+        .map_err(auth::redis_command_error("SET"))?;
+
+    Ok(json!({
+        "publicKey": {
+            "rp": { "id": CONFIG.rp_id, "name": CONFIG.rp_name },
+            "user": { "id": user_handle(user.id), "name": user.username, "displayName": user.username },
+            "challenge": challenge,
+            "pubKeyCredParams": [
+                { "type": "public-key", "alg": COSE_ALG_ES256 },
+                { "type": "public-key", "alg": COSE_ALG_RS256 },
+            ],
+            "timeout": CHALLENGE_TTL_SECONDS * 1000,
+            "attestation": "none",
+            "excludeCredentials": credential_descriptors(existing),
+            "authenticatorSelection": { "residentKey": "preferred", "userVerification": "required" },
+        }
+    }))
+}
+
+pub async fn finish_registration(
+    redis_client: &redis::Client,
+    user_id: i32,
+    credential: &RegistrationCredential,
+) -> Result<NewCredential, AppError> {
+    let client_data_json = decode_b64(&credential.response.client_data_json, "clientDataJSON")?;
+    let client_data = parse_client_data(&client_data_json, "webauthn.create")?;
+
+    // El reto es de un solo uso
+    let mut conn = auth::redis_connection(redis_client).await?;
+    let expected: Option<String> = conn
+        .get_del(format!("webauthn_register:{}", user_id))
+        .await
+        .map_err(auth::redis_command_error("GETDEL"))?;
+    if expected.as_deref() != Some(client_data.challenge.as_str()) {
+        return Err(invalid("reto de registro desconocido o caducado"));
+    }
+
+    // attestationObject = CBOR { fmt, attStmt, authData }
+    let attestation = decode_b64(&credential.response.attestation_object, "attestationObject")?;
+    let attestation = ciborium::de::from_reader::<Value, _>(attestation.as_slice())
+        .ok()
+        .and_then(|value| value.into_map().ok())
+        .ok_or_else(|| invalid("attestationObject ilegible"))?;
+    let auth_data = attestation
+        .iter()
+        .find(|(key, _)| key.as_text() == Some("authData"))
+        .and_then(|(_, value)| value.as_bytes())
+        .ok_or_else(|| invalid("attestationObject sin authData"))?;
+
+    let auth_data = parse_authenticator_data(auth_data)?;
+    check_authenticator_flags(&auth_data)?;
+
+    let (credential_id, public_key) = auth_data.attested.ok_or_else(|| invalid("sin credencial atestada"))?;
+    let credential_id = URL_SAFE_NO_PAD.encode(credential_id);
+    if credential_id != credential.id.trim_end_matches('=') {
+        return Err(invalid("el id no coincide con authenticatorData"));
+    }
+
+    let algorithm = cose_algorithm(&public_key)?;
+
+    Ok(NewCredential {
+        credential_id,
+        public_key,
+        algorithm: algorithm as i32,
+        sign_count: auth_data.sign_count as i64,
+        transports: credential.response.transports.clone(),
+    })
+}
+
+// ----------------------------------------------------------------------------
+// LOGIN (assertion)
+// ----------------------------------------------------------------------------
+
+/// Opciones para navigator.credentials.get(). Sin usuario, el navegador
+/// ofrece las passkeys "descubribles" guardadas para este dominio.
+pub async fn start_authentication(
+    redis_client: &redis::Client,
+    user_id: Option<i32>,
+    allowed: &[WebAuthnCredential],
+) -> Result<serde_json::Value, AppError> {
+    let challenge = new_challenge();
+
+    let mut conn = auth::redis_connection(redis_client).await?;
+    let _: () = conn
+        .set_ex(
+            format!("webauthn_login:{}", challenge),
+            serde_json::to_string(&PendingAssertion { user_id }).unwrap_or_default(),
+            CHALLENGE_TTL_SECONDS,
+        )
+        .await
+        .map_err(auth::redis_command_error("SET"))?;
+
+    Ok(json!({
+        "publicKey": {
+            "challenge": challenge,
+            "rpId": CONFIG.rp_id,
+            "timeout": CHALLENGE_TTL_SECONDS * 1000,
+            "userVerification": "required",
+            "allowCredentials": credential_descriptors(allowed),
+        }
+    }))
+}
+
+/// Consume el reto del clientDataJSON. Devuelve el usuario al que se ligó (si se ligó).
+pub async fn consume_authentication_challenge(
+    redis_client: &redis::Client,
+    assertion: &AssertionCredential,
+) -> Result<Option<i32>, AppError> {
+    let client_data_json = decode_b64(&assertion.response.client_data_json, "clientDataJSON")?;
+    let client_data = parse_client_data(&client_data_json, "webauthn.get")?;
+
+    let mut conn = auth::redis_connection(redis_client).await?;
+    let pending: Option<String> = conn
+        .get_del(format!("webauthn_login:{}", client_data.challenge))
+        .await
+        .map_err(auth::redis_command_error("GETDEL"))?;
+
+    let pending: PendingAssertion = pending
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .ok_or_else(|| invalid("reto de login desconocido o caducado"))?;
+    Ok(pending.user_id)
+}
+
+/// Verifica la firma con la clave guardada. Devuelve el nuevo contador de firmas.
+pub fn verify_assertion(credential: &WebAuthnCredential, assertion: &AssertionCredential) -> Result<i64, AppError> {
+    if let Some(handle) = &assertion.response.user_handle {
+        if handle.trim_end_matches('=') != user_handle(credential.user_id) {
+            return Err(invalid("userHandle no coincide con el dueño de la credencial"));
+        }
+    }
+
+    let authenticator_data = decode_b64(&assertion.response.authenticator_data, "authenticatorData")?;
+    let client_data_json = decode_b64(&assertion.response.client_data_json, "clientDataJSON")?;
+    let signature = decode_b64(&assertion.response.signature, "signature")?;
+
+    let auth_data = parse_authenticator_data(&authenticator_data)?;
+    check_authenticator_flags(&auth_data)?;
+
+    // Se firma authenticatorData || SHA-256(clientDataJSON)
+    let mut message = authenticator_data.clone();
+    message.extend_from_slice(&Sha256::digest(&client_data_json));
+    verify_signature(&credential.public_key, credential.algorithm as i64, &message, &signature)?;
+
+    let sign_count = auth_data.sign_count as i64;
+    if !sign_count_advances(credential.sign_count, sign_count) {
+        tracing::warn!(
+            security_event = "webauthn_counter_regression",
+            "Contador de firmas no avanza en la credencial {} del usuario {}",
+            credential.id, credential.user_id
+        );
+        return Err(invalid("contador de firmas no avanza"));
+    }
+
+    Ok(sign_count)
+}
+
+/// Contador: si no avanza (y el autenticador lo usa), puede ser un clon de la clave
+fn sign_count_advances(stored: i64, received: i64) -> bool {
+    (received == 0 && stored == 0) || received > stored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+
+    fn cbor(value: Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&value, &mut bytes).expect("CBOR serializable");
+        bytes
+    }
+
+    fn int(value: i64) -> Value {
+        Value::Integer(value.into())
+    }
+
+    fn es256_cose_key(key: &SigningKey) -> Vec<u8> {
+        let point = key.verifying_key().to_encoded_point(false);
+        cbor(Value::Map(vec![
+            (int(1), int(2)),
+            (int(3), int(COSE_ALG_ES256)),
+            (int(-1), int(1)),
+            (int(-2), Value::Bytes(point.x().unwrap().to_vec())),
+            (int(-3), Value::Bytes(point.y().unwrap().to_vec())),
+        ]))
+    }
+
+    /// rpIdHash | flags | signCount | [aaguid | idLen | id | clave] | extra
+    fn authenticator_data(flags: u8, id_len: u16, id: &[u8], key: &[u8], extra: &[u8]) -> Vec<u8> {
+        let mut data = vec![0u8; 32];
+        data.push(flags);
+        data.extend_from_slice(&7u32.to_be_bytes());
+        data.extend_from_slice(&[0u8; 16]);
+        data.extend_from_slice(&id_len.to_be_bytes());
+        data.extend_from_slice(id);
+        data.extend_from_slice(key);
+        data.extend_from_slice(extra);
+        data
+    }
+
+    #[test]
+    fn rejects_truncated_authenticator_data() {
+        assert!(parse_authenticator_data(&[0u8; 36]).is_err());
+
+        // Anuncia credencial atestada pero no trae ni el aaguid completo
+        let mut data = vec![0u8; 32];
+        data.push(FLAG_ATTESTED_CREDENTIAL);
+        data.extend_from_slice(&[0u8; 4 + 10]);
+        assert!(parse_authenticator_data(&data).is_err());
+    }
+
+    #[test]
+    fn without_attested_flag_there_is_no_credential() {
+        let key = es256_cose_key(&SigningKey::random(&mut rand::rngs::OsRng));
+        let data = authenticator_data(FLAG_USER_PRESENT | FLAG_USER_VERIFIED, 16, &[1u8; 16], &key, &[]);
+
+        let parsed = parse_authenticator_data(&data).unwrap();
+        assert_eq!(parsed.sign_count, 7);
+        assert!(parsed.attested.is_none());
+    }
+
+    #[test]
+    fn rejects_credential_id_longer_than_the_data() {
+        let data = authenticator_data(FLAG_ATTESTED_CREDENTIAL, 64, &[1u8; 16], &[], &[]);
+        assert!(parse_authenticator_data(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_credential_id_over_the_spec_limit() {
+        let key = es256_cose_key(&SigningKey::random(&mut rand::rngs::OsRng));
+        let id = vec![1u8; MAX_CREDENTIAL_ID_LENGTH + 1];
+        let data = authenticator_data(FLAG_ATTESTED_CREDENTIAL, id.len() as u16, &id, &key, &[]);
+        assert!(parse_authenticator_data(&data).is_err());
+    }
+
+    #[test]
+    fn separates_the_cose_key_from_the_extensions() {
+        let key = es256_cose_key(&SigningKey::random(&mut rand::rngs::OsRng));
+        let extensions = cbor(Value::Map(vec![(Value::Text("credProtect".to_string()), int(2))]));
+        let data = authenticator_data(FLAG_ATTESTED_CREDENTIAL, 16, &[9u8; 16], &key, &extensions);
+
+        let (credential_id, public_key) = parse_authenticator_data(&data).unwrap().attested.unwrap();
+        assert_eq!(credential_id, vec![9u8; 16]);
+        assert_eq!(public_key, key);
+    }
+
+    #[test]
+    fn decodes_supported_cose_keys() {
+        let es256 = es256_cose_key(&SigningKey::random(&mut rand::rngs::OsRng));
+        assert_eq!(cose_algorithm(&es256).unwrap(), COSE_ALG_ES256);
+
+        let rs256 = cbor(Value::Map(vec![
+            (int(1), int(3)),
+            (int(3), int(COSE_ALG_RS256)),
+            (int(-1), Value::Bytes(vec![0xc5; 256])),
+            (int(-2), Value::Bytes(vec![1, 0, 1])),
+        ]));
+        assert_eq!(cose_algorithm(&rs256).unwrap(), COSE_ALG_RS256);
+    }
+
+    #[test]
+    fn rejects_unsupported_or_malformed_cose_keys() {
+        // EC2 en otra curva (P-384), EdDSA, CBOR que no es un mapa y bytes sueltos
+        let p384 = cbor(Value::Map(vec![(int(1), int(2)), (int(3), int(COSE_ALG_ES256)), (int(-1), int(2))]));
+        let eddsa = cbor(Value::Map(vec![(int(1), int(1)), (int(3), int(-8))]));
+        let not_a_map = cbor(Value::Array(vec![int(1), int(2)]));
+
+        for key in [p384, eddsa, not_a_map, vec![0xff, 0x00, 0x13]] {
+            assert!(cose_algorithm(&key).is_err());
+        }
+    }
+
+    #[test]
+    fn verifies_es256_signatures() {
+        let signing_key = SigningKey::random(&mut rand::rngs::OsRng);
+        let cose_key = es256_cose_key(&signing_key);
+        let signature: Signature = signing_key.sign(b"authData || hash");
+        let der = signature.to_der();
+
+        assert!(verify_signature(&cose_key, COSE_ALG_ES256, b"authData || hash", der.as_bytes()).is_ok());
+        assert!(verify_signature(&cose_key, COSE_ALG_ES256, b"otro mensaje", der.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn sign_count_must_advance_unless_unused() {
+        assert!(sign_count_advances(0, 0)); // autenticador sin contador
+        assert!(sign_count_advances(0, 1));
+        assert!(sign_count_advances(5, 6));
+        assert!(!sign_count_advances(5, 5));
+        assert!(!sign_count_advances(5, 3));
+        assert!(!sign_count_advances(5, 0));
+    }
+}
@@ -0,0 +1,133 @@
+// ============================================================================
+// UTILIDADES DE LOS TESTS DE INTEGRACIÓN
+// ============================================================================
+//
+// Cada test arranca el binario real de la API en un puerto libre, contra el
+// Postgres y el Redis de DATABASE_URL / REDIS_URL (variables de entorno o
+// .env), y habla con ella por HTTP como lo haría un cliente.
+//
+// Los datos que crea (usuarios, passkeys, identidades) llevan un sufijo
+// aleatorio, así que los tests pueden repetirse sobre la misma base de datos.
+//
+// ============================================================================
+
+#![allow(dead_code)] // Cada test usa solo parte de estas utilidades
+
+use serde_json::{json, Value as Json};
+use std::{
+    net::TcpListener,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// La API corriendo en un proceso hijo; se mata al salir del test
+pub struct TestApi {
+    pub url: String,
+    pub http: reqwest::Client,
+    child: Child,
+    scratch_dir: PathBuf,
+}
+
+impl TestApi {
+    /// Arranca la API con la configuración de test más `extra_env`
+    pub async fn spawn(extra_env: &[(&str, &str)]) -> TestApi {
+        dotenvy::dotenv().ok();
+
+        let port = free_port();
+        let url = format!("http://localhost:{}", port);
+        let scratch_dir = std::env::temp_dir().join(format!("rust-api-test-{}", unique_suffix()));
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_hello_world"));
+        command
+            .env("PORT", port.to_string())
+            .env("APP_BASE_URL", &url)
+            .env("RATE_LIMIT_PER_SECOND", "10000")
+            .env("REQUIRE_EMAIL_VERIFICATION", "false")
+            .env("AUTH_COOKIE_SECURE", "false")
+            .env("MAILER", "file")
+            .env("MAIL_OUTBOX_DIR", scratch_dir.join("outbox"))
+            .env("STORAGE_BACKEND", "local")
+            .env("STORAGE_LOCAL_DIR", scratch_dir.join("uploads"))
+            .env("RUST_LOG", "warn")
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit());
+        for (name, value) in extra_env {
+            command.env(name, value);
+        }
+
+        let child = command.spawn().expect("no se pudo arrancar la API");
+        let mut api = TestApi { url, http: reqwest::Client::new(), child, scratch_dir };
+        api.wait_until_ready().await;
+        api
+    }
+
+    async fn wait_until_ready(&mut self) {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.child.try_wait().expect("estado del proceso de la API") {
+                panic!("La API terminó al arrancar ({}). ¿Están Postgres y Redis accesibles?", status);
+            }
+            if let Ok(response) = self.http.get(format!("{}/health", self.url)).send().await {
+                if response.status().is_success() {
+                    return;
+                }
+            }
+            assert!(started.elapsed() < STARTUP_TIMEOUT, "La API no respondió en {:?}", STARTUP_TIMEOUT);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    /// POST JSON; devuelve el status y el cuerpo (Null si no es JSON)
+    pub async fn post(&self, path: &str, token: Option<&str>, body: Json) -> (u16, Json) {
+        let mut request = self.http.post(format!("{}{}", self.url, path)).json(&body);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.expect("API inaccesible");
+        let status = response.status().as_u16();
+        (status, response.json().await.unwrap_or(Json::Null))
+    }
+
+    /// Registra un usuario nuevo y devuelve (username, access token)
+    pub async fn register_user(&self, prefix: &str) -> (String, String) {
+        let username = format!("{}_{}", prefix, unique_suffix());
+        let (status, body) = self
+            .post(
+                "/register",
+                None,
+                json!({
+                    "username": username,
+                    "email": format!("{}@example.com", username),
+                    "password": "Correct-Horse-42x",
+                }),
+            )
+            .await;
+        assert_eq!(status, 200, "registro fallido: {}", body);
+        let token = body["access_token"].as_str().expect("registro sin access_token").to_string();
+        (username, token)
+    }
+}
+
+impl Drop for TestApi {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.scratch_dir);
+    }
+}
+
+/// Puerto que el sistema acaba de dar por libre
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("puerto libre")
+}
+
+/// Sufijo corto para nombres únicos entre ejecuciones
+pub fn unique_suffix() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..12].to_string()
+}
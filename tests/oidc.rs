@@ -0,0 +1,327 @@
+// ============================================================================
+// TEST DE INTEGRACIÓN: LOGIN OIDC CONTRA UN IdP FALSO
+// ============================================================================
+//
+// Levanta en el propio test lo mínimo de un proveedor OpenID Connect:
+//   GET  /.well-known/openid-configuration
+//   GET  /jwks
+//   GET  /authorize   -> "autentica" al instante y redirige con ?code&state
+//   POST /token       -> comprueba PKCE y devuelve un ID token RS256
+//
+// y arranca la API apuntando a él. El test hace de navegador: sigue las
+// redirecciones a mano y guarda la cookie del state.
+//
+// El usuario se elige con ?login_hint=<usuario>; email = <usuario>@example.com.
+//
+// Necesita Postgres y Redis (DATABASE_URL / REDIS_URL):
+//   cargo test --test oidc -- --ignored
+//
+// ============================================================================
+
+mod common;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use common::{unique_suffix, TestApi};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::{header, redirect::Policy};
+use rsa::{pkcs1::EncodeRsaPrivateKey, traits::PublicKeyParts, RsaPrivateKey};
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+const KEY_ID: &str = "mock-key-1";
+const CLIENT_ID: &str = "rust-api";
+
+// ----------------------------------------------------------------------------
+// IdP falso
+// ----------------------------------------------------------------------------
+
+struct AuthorizationCode {
+    client_id: String,
+    redirect_uri: String,
+    nonce: Option<String>,
+    code_challenge: String,
+    username: String,
+}
+
+#[derive(Clone)]
+struct Issuer {
+    url: String,
+    codes: Arc<Mutex<HashMap<String, AuthorizationCode>>>,
+}
+
+#[derive(Deserialize)]
+struct AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+    state: String,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+    login_hint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    client_id: String,
+    code_verifier: String,
+}
+
+/// Clave de firma del IdP y su JWK. Generar RSA es lento: una por binario de test
+fn signing_key() -> &'static (EncodingKey, JsonValue) {
+    static KEY: OnceLock<(EncodingKey, JsonValue)> = OnceLock::new();
+    KEY.get_or_init(|| {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).expect("clave RSA");
+        let pem = private_key.to_pkcs1_pem(Default::default()).expect("PEM de la clave RSA");
+        let public_key = private_key.to_public_key();
+        let jwk = json!({
+            "kty": "RSA",
+            "use": "sig",
+            "alg": "RS256",
+            "kid": KEY_ID,
+            "n": URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+            "e": URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+        });
+        (EncodingKey::from_rsa_pem(pem.as_bytes()).expect("clave de firma"), jwk)
+    })
+}
+
+fn oauth_error(error: &str) -> axum::response::Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response()
+}
+
+async fn discovery(State(issuer): State<Issuer>) -> Json<JsonValue> {
+    Json(json!({
+        "issuer": issuer.url,
+        "authorization_endpoint": format!("{}/authorize", issuer.url),
+        "token_endpoint": format!("{}/token", issuer.url),
+        "jwks_uri": format!("{}/jwks", issuer.url),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+async fn jwks() -> Json<JsonValue> {
+    Json(json!({ "keys": [signing_key().1] }))
+}
+
+async fn authorize(State(issuer): State<Issuer>, Query(query): Query<AuthorizeQuery>) -> axum::response::Response {
+    // Este IdP solo acepta PKCE S256, como exigiría uno real para clientes públicos
+    let code_challenge = match (query.code_challenge, query.code_challenge_method.as_deref()) {
+        (Some(challenge), Some("S256")) => challenge,
+        _ => return oauth_error("invalid_request"),
+    };
+
+    let code = uuid::Uuid::new_v4().to_string();
+    issuer.codes.lock().unwrap().insert(
+        code.clone(),
+        AuthorizationCode {
+            client_id: query.client_id,
+            redirect_uri: query.redirect_uri.clone(),
+            nonce: query.nonce,
+            code_challenge,
+            username: query.login_hint.unwrap_or_else(|| "mock_user".to_string()),
+        },
+    );
+
+    let mut redirect = url::Url::parse(&query.redirect_uri).expect("redirect_uri inválido");
+    redirect.query_pairs_mut().append_pair("code", &code).append_pair("state", &query.state);
+    Redirect::to(redirect.as_str()).into_response()
+}
+
+async fn token(State(issuer): State<Issuer>, Form(form): Form<TokenForm>) -> axum::response::Response {
+    if form.grant_type != "authorization_code" {
+        return oauth_error("unsupported_grant_type");
+    }
+
+    // Los codes son de un solo uso
+    let Some(grant) = issuer.codes.lock().unwrap().remove(&form.code) else {
+        return oauth_error("invalid_grant");
+    };
+
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(form.code_verifier.as_bytes()));
+    if grant.client_id != form.client_id || grant.redirect_uri != form.redirect_uri || grant.code_challenge != challenge {
+        return oauth_error("invalid_grant");
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let claims = json!({
+        "iss": issuer.url,
+        "sub": format!("mock|{}", grant.username),
+        "aud": grant.client_id,
+        "iat": now,
+        "exp": now + 300,
+        "nonce": grant.nonce,
+        "email": format!("{}@example.com", grant.username),
+        "email_verified": true,
+        "preferred_username": grant.username,
+    });
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(KEY_ID.to_string());
+    let id_token = jsonwebtoken::encode(&header, &claims, &signing_key().0).expect("firma del ID token");
+
+    Json(json!({
+        "access_token": uuid::Uuid::new_v4().to_string(),
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token,
+    }))
+    .into_response()
+}
+
+/// Arranca el IdP en un puerto libre y devuelve su URL (que es también el issuer)
+async fn start_issuer() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("puerto del IdP");
+    let url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    let issuer = Issuer { url: url.clone(), codes: Arc::new(Mutex::new(HashMap::new())) };
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/jwks", get(jwks))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .with_state(issuer);
+
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    url
+}
+
+// ----------------------------------------------------------------------------
+// Navegador
+// ----------------------------------------------------------------------------
+
+struct Browser {
+    http: reqwest::Client,
+}
+
+impl Browser {
+    fn new() -> Self {
+        Browser { http: reqwest::Client::builder().redirect(Policy::none()).build().unwrap() }
+    }
+
+    /// GET que espera una redirección; devuelve el Location y las cookies que fija
+    async fn follow(&self, url: &str) -> (String, Vec<String>) {
+        let response = self.http.get(url).send().await.expect("petición del navegador");
+        assert!(response.status().is_redirection(), "{} no redirige: {}", url, response.status());
+        let location = response.headers()[header::LOCATION].to_str().unwrap().to_string();
+        let cookies = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok()?.split(';').next().map(str::to_owned))
+            .collect();
+        (location, cookies)
+    }
+
+    /// Inicia el login en la API y pasa por el IdP. Devuelve la URL del
+    /// callback (con code y state) y la cookie del state
+    async fn authorize(&self, api: &TestApi, username: &str) -> (String, String) {
+        let (authorize_url, cookies) = self.follow(&format!("{}/auth/oidc/login?login_hint={}", api.url, username)).await;
+        let state_cookie = cookies
+            .into_iter()
+            .find(|cookie| cookie.starts_with("oidc_state="))
+            .expect("la API no fijó la cookie del state");
+        let (callback_url, _) = self.follow(&authorize_url).await;
+        (callback_url, state_cookie)
+    }
+
+    async fn callback(&self, callback_url: &str, cookie: Option<&str>) -> (u16, JsonValue) {
+        let mut request = self.http.get(callback_url);
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let response = request.send().await.expect("callback inaccesible");
+        let status = response.status().as_u16();
+        (status, response.json().await.unwrap_or(JsonValue::Null))
+    }
+}
+
+async fn spawn_api_with_issuer() -> TestApi {
+    let issuer_url = start_issuer().await;
+    TestApi::spawn(&[("OIDC_ISSUER_URL", issuer_url.as_str()), ("OIDC_CLIENT_ID", CLIENT_ID)]).await
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[tokio::test]
+#[ignore = "necesita Postgres y Redis: cargo test -- --ignored"]
+async fn oidc_login_provisions_the_user_and_returns_tokens() {
+    let api = spawn_api_with_issuer().await;
+    let browser = Browser::new();
+    let username = format!("oidc_{}", unique_suffix());
+
+    // Primer login: alta JIT. El segundo entra en la misma cuenta
+    for _ in 0..2 {
+        let (callback_url, state_cookie) = browser.authorize(&api, &username).await;
+        let (status, tokens) = browser.callback(&callback_url, Some(&state_cookie)).await;
+        assert_eq!(status, 200, "callback rechazado: {}", tokens);
+
+        let access_token = tokens["access_token"].as_str().expect("callback sin access_token");
+        let me: JsonValue = api
+            .http
+            .get(format!("{}/me", api.url))
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(me["username"], username.as_str());
+    }
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres y Redis: cargo test -- --ignored"]
+async fn callback_requires_the_state_cookie_of_the_browser_that_started_it() {
+    let api = spawn_api_with_issuer().await;
+    let victim = Browser::new();
+    let attacker = Browser::new();
+    let username = format!("oidc_{}", unique_suffix());
+
+    // Un callback ajeno (CSRF de login) no vale ni sin cookie ni con la de otro login
+    let (callback_url, state_cookie) = attacker.authorize(&api, &username).await;
+    let (_, other_cookie) = victim.authorize(&api, &username).await;
+    let (status, _) = victim.callback(&callback_url, None).await;
+    assert_eq!(status, 401);
+    let (status, _) = victim.callback(&callback_url, Some(&other_cookie)).await;
+    assert_eq!(status, 401);
+
+    // El navegador que lo inició sí puede completarlo
+    let (status, tokens) = attacker.callback(&callback_url, Some(&state_cookie)).await;
+    assert_eq!(status, 200, "callback rechazado: {}", tokens);
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres y Redis: cargo test -- --ignored"]
+async fn callback_cannot_be_replayed() {
+    let api = spawn_api_with_issuer().await;
+    let browser = Browser::new();
+    let username = format!("oidc_{}", unique_suffix());
+
+    let (callback_url, state_cookie) = browser.authorize(&api, &username).await;
+    let (status, _) = browser.callback(&callback_url, Some(&state_cookie)).await;
+    assert_eq!(status, 200);
+
+    let (status, _) = browser.callback(&callback_url, Some(&state_cookie)).await;
+    assert_eq!(status, 401, "un state ya usado no debe aceptarse");
+}
@@ -0,0 +1,183 @@
+// ============================================================================
+// TEST DE INTEGRACIÓN: PASSKEYS (WEBAUTHN)
+// ============================================================================
+//
+// Hace de navegador + autenticador (clave P-256 en memoria) contra la API real:
+//   1. Registra una passkey para un usuario recién creado
+//   2. Hace login con ella (indicando el usuario y como passkey descubrible)
+//   3. Repite una assertion ya usada: la API debe rechazarla
+//
+// Necesita Postgres y Redis (DATABASE_URL / REDIS_URL):
+//   cargo test --test webauthn -- --ignored
+//
+// ============================================================================
+
+mod common;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value;
+use common::TestApi;
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use rand::{rngs::OsRng, RngCore};
+use serde_json::{json, Value as Json};
+use sha2::{Digest, Sha256};
+
+// Flags de authenticatorData: usuario presente, verificado y credencial atestada
+const FLAGS_ASSERTION: u8 = 0x01 | 0x04;
+const FLAGS_REGISTRATION: u8 = FLAGS_ASSERTION | 0x40;
+
+fn cbor(value: Value) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(&value, &mut bytes).expect("CBOR serializable");
+    bytes
+}
+
+struct Authenticator {
+    key: SigningKey,
+    credential_id: Vec<u8>,
+    rp_id: String,
+    origin: String,
+    sign_count: u32,
+}
+
+impl Authenticator {
+    fn new(rp_id: &str, origin: &str) -> Self {
+        let mut credential_id = vec![0u8; 16];
+        OsRng.fill_bytes(&mut credential_id);
+        Authenticator {
+            key: SigningKey::random(&mut OsRng),
+            credential_id,
+            rp_id: rp_id.to_string(),
+            origin: origin.to_string(),
+            sign_count: 0,
+        }
+    }
+
+    fn credential_id(&self) -> String {
+        URL_SAFE_NO_PAD.encode(&self.credential_id)
+    }
+
+    fn client_data(&self, ceremony: &str, challenge: &str) -> Vec<u8> {
+        json!({ "type": ceremony, "challenge": challenge, "origin": self.origin })
+            .to_string()
+            .into_bytes()
+    }
+
+    fn authenticator_data(&mut self, flags: u8) -> Vec<u8> {
+        self.sign_count += 1;
+        let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        data
+    }
+
+    /// Clave pública en COSE: kty EC2, alg ES256, crv P-256, x, y
+    fn cose_key(&self) -> Vec<u8> {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        cbor(Value::Map(vec![
+            (Value::Integer(1.into()), Value::Integer(2.into())),
+            (Value::Integer(3.into()), Value::Integer((-7).into())),
+            (Value::Integer((-1).into()), Value::Integer(1.into())),
+            (Value::Integer((-2).into()), Value::Bytes(point.x().expect("punto sin x").to_vec())),
+            (Value::Integer((-3).into()), Value::Bytes(point.y().expect("punto sin y").to_vec())),
+        ]))
+    }
+
+    /// Respuesta a navigator.credentials.create() con attestation "none"
+    fn create(&mut self, options: &Json) -> Json {
+        let challenge = options["publicKey"]["challenge"].as_str().expect("reto de registro");
+
+        let mut auth_data = self.authenticator_data(FLAGS_REGISTRATION);
+        auth_data.extend_from_slice(&[0u8; 16]); // aaguid
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        auth_data.extend_from_slice(&self.cose_key());
+
+        let attestation = cbor(Value::Map(vec![
+            (Value::Text("fmt".to_string()), Value::Text("none".to_string())),
+            (Value::Text("attStmt".to_string()), Value::Map(Vec::new())),
+            (Value::Text("authData".to_string()), Value::Bytes(auth_data)),
+        ]));
+
+        json!({
+            "id": self.credential_id(),
+            "name": "Software authenticator",
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(self.client_data("webauthn.create", challenge)),
+                "attestationObject": URL_SAFE_NO_PAD.encode(attestation),
+                "transports": ["internal"],
+            }
+        })
+    }
+
+    /// Respuesta a navigator.credentials.get(): firma authData || SHA-256(clientDataJSON)
+    fn get(&mut self, options: &Json, user_handle: &str) -> Json {
+        let challenge = options["publicKey"]["challenge"].as_str().expect("reto de login");
+
+        let auth_data = self.authenticator_data(FLAGS_ASSERTION);
+        let client_data = self.client_data("webauthn.get", challenge);
+
+        let mut message = auth_data.clone();
+        message.extend_from_slice(&Sha256::digest(&client_data));
+        let signature: Signature = self.key.sign(&message);
+
+        json!({
+            "id": self.credential_id(),
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "authenticatorData": URL_SAFE_NO_PAD.encode(auth_data),
+                "signature": URL_SAFE_NO_PAD.encode(signature.to_der().as_bytes()),
+                "userHandle": user_handle,
+            }
+        })
+    }
+}
+
+/// Crea un usuario y le registra una passkey. Devuelve (username, user handle, autenticador)
+async fn user_with_passkey(api: &TestApi) -> (String, String, Authenticator) {
+    let (username, access_token) = api.register_user("passkey").await;
+
+    let (status, options) = api.post("/webauthn/register/start", Some(&access_token), json!({})).await;
+    assert_eq!(status, 200, "opciones de registro: {}", options);
+    let rp_id = options["publicKey"]["rp"]["id"].as_str().expect("opciones de registro sin rp.id");
+    let user_handle = options["publicKey"]["user"]["id"].as_str().expect("opciones sin user.id").to_string();
+
+    let mut authenticator = Authenticator::new(rp_id, &api.url);
+    let credential = authenticator.create(&options);
+    let (status, stored) = api.post("/webauthn/register/finish", Some(&access_token), credential).await;
+    assert_eq!(status, 201, "registro rechazado: {}", stored);
+
+    (username, user_handle, authenticator)
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres y Redis: cargo test -- --ignored"]
+async fn passkey_registration_and_login() {
+    let api = TestApi::spawn(&[]).await;
+    let (username, user_handle, mut authenticator) = user_with_passkey(&api).await;
+
+    // Con username y como passkey descubrible (sin username)
+    for body in [json!({ "username": username }), json!({})] {
+        let (_, options) = api.post("/webauthn/login/start", None, body).await;
+        let assertion = authenticator.get(&options, &user_handle);
+        let (status, tokens) = api.post("/webauthn/login/finish", None, assertion).await;
+        assert_eq!(status, 200, "login con passkey rechazado: {}", tokens);
+        assert!(tokens["access_token"].is_string() && tokens["refresh_token"].is_string());
+    }
+}
+
+#[tokio::test]
+#[ignore = "necesita Postgres y Redis: cargo test -- --ignored"]
+async fn replayed_assertion_is_rejected() {
+    let api = TestApi::spawn(&[]).await;
+    let (username, user_handle, mut authenticator) = user_with_passkey(&api).await;
+
+    let (_, options) = api.post("/webauthn/login/start", None, json!({ "username": username })).await;
+    let assertion = authenticator.get(&options, &user_handle);
+    let (status, _) = api.post("/webauthn/login/finish", None, assertion.clone()).await;
+    assert_eq!(status, 200);
+
+    // El reto es de un solo uso
+    let (status, _) = api.post("/webauthn/login/finish", None, assertion).await;
+    assert_eq!(status, 401, "una assertion repetida no debe aceptarse");
+}